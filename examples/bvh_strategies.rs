@@ -0,0 +1,74 @@
+// Compares the BVH construction strategies on an uneven scene: a huge ground sphere with a field
+// of small spheres on top of it. Prints the shape of each tree and how fast it traces rays.
+//
+//     cargo run --release --example bvh_strategies
+
+use std::time::Instant;
+
+use bumpalo::Bump;
+use rand::{Rng, SeedableRng};
+use raytracing::{
+    hittable::{
+        bvh::{Bvh, BvhStrategy},
+        Hittable, Interval, Sphere,
+    },
+    material::Lambertian,
+    ray::Ray,
+    time_utils::Unchanging,
+    units::{random_unit_vector, Color, Point, RenderRng},
+};
+
+const RAYS: usize = 1_000_000;
+
+fn main() {
+    let bump = Bump::new();
+    let mut rng = RenderRng::seed_from_u64(0);
+
+    let material = bump.alloc(Lambertian::new_with_color(Color::splat(0.5), &bump));
+    let mut objects: Vec<&dyn Hittable> = vec![bump.alloc(Sphere::<Unchanging>::new(
+        Point::new(0., -1000., 0.),
+        1000.,
+        material,
+    ))];
+    for a in -50..50 {
+        for b in -50..50 {
+            let center = Point::new(
+                a as f32 + 0.9 * rng.gen::<f32>(),
+                0.2,
+                b as f32 + 0.9 * rng.gen::<f32>(),
+            );
+            objects.push(bump.alloc(Sphere::<Unchanging>::new(center, 0.2, material)));
+        }
+    }
+
+    // the same rays for every strategy, from above the field in random directions
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| Ray {
+            origin: Point::new(
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(0.5..5.0),
+                rng.gen_range(-50.0..50.0),
+            ),
+            direct: random_unit_vector(&mut rng),
+            time: 0.,
+        })
+        .collect();
+
+    println!("{} objects, {} rays", objects.len(), rays.len());
+    for (name, strategy) in [("median", BvhStrategy::Median), ("sah", BvhStrategy::SAH)] {
+        let (bvh, stats) = Bvh::build(objects.clone(), strategy);
+
+        let start = Instant::now();
+        let hits = rays
+            .iter()
+            .filter(|ray| bvh.hit(ray, Interval::<f32>::POSITIVE).is_some())
+            .count();
+        let elapsed = start.elapsed();
+
+        println!("{name}: {stats}");
+        println!(
+            "    traced in {elapsed:.2?} ({:.2} Mrays/s, {hits} hits)",
+            RAYS as f64 / elapsed.as_secs_f64() / 1e6
+        );
+    }
+}
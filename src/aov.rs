@@ -0,0 +1,104 @@
+use std::ops::{Index, IndexMut};
+
+use crate::units::Color;
+
+// Arbitrary output variables: buffers rendered alongside the image for compositing. All but the
+// lighting ones describe what the camera ray hit first, and are zero where it hit nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    // the distance from the camera
+    Depth,
+    // the world space shading normal, facing the camera
+    Normal,
+    Albedo,
+    Uv,
+    ObjectId,
+    MaterialId,
+    // light that reached the first hit straight from a light or the background
+    Direct,
+    // light that bounced at least once more on the way
+    Indirect,
+    // light given off by what the camera ray hit, or the background behind it; together with
+    // `Direct` and `Indirect` it makes up the image, except where a filter's negative lobes had to
+    // be clamped
+    Emission,
+}
+
+// What one sample found for every AOV.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AovSample([Color; Aov::ALL.len()]);
+
+impl Aov {
+    pub const ALL: [Self; 9] = [
+        Self::Depth,
+        Self::Normal,
+        Self::Albedo,
+        Self::Uv,
+        Self::ObjectId,
+        Self::MaterialId,
+        Self::Direct,
+        Self::Indirect,
+        Self::Emission,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Depth => "depth",
+            Self::Normal => "normal",
+            Self::Albedo => "albedo",
+            Self::Uv => "uv",
+            Self::ObjectId => "object_id",
+            Self::MaterialId => "material_id",
+            Self::Direct => "direct",
+            Self::Indirect => "indirect",
+            Self::Emission => "emission",
+        }
+    }
+
+    // the names of the channels it has in an EXR, each taking a component of its colors
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Self::Depth => &["Z"],
+            Self::Normal => &["X", "Y", "Z"],
+            Self::Uv => &["U", "V"],
+            Self::ObjectId | Self::MaterialId => &["id"],
+            Self::Albedo | Self::Direct | Self::Indirect | Self::Emission => &["R", "G", "B"],
+        }
+    }
+
+    // the lighting AOVs are filtered like the image, so that they add up to it; the others are
+    // averaged over the samples taken for each pixel, which keeps filters from mixing in the
+    // depth of the background or pushing normals out of range
+    pub fn is_lighting(self) -> bool {
+        matches!(self, Self::Direct | Self::Indirect | Self::Emission)
+    }
+
+    // IDs can't be averaged at all, so their pixels keep the value of their first sample
+    pub fn is_id(self) -> bool {
+        matches!(self, Self::ObjectId | Self::MaterialId)
+    }
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self([Color::ZERO; Aov::ALL.len()])
+    }
+}
+
+impl Index<Aov> for AovSample {
+    type Output = Color;
+
+    fn index(&self, aov: Aov) -> &Color {
+        &self.0[aov as usize]
+    }
+}
+
+impl IndexMut<Aov> for AovSample {
+    fn index_mut(&mut self, aov: Aov) -> &mut Color {
+        &mut self.0[aov as usize]
+    }
+}
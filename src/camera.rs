@@ -1,13 +1,22 @@
-use std::io::{BufWriter, Write};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use indicatif::ParallelProgressIterator;
-use rand::random;
+use rand::Rng;
 use rayon::prelude::*;
 
 use crate::{
-    hittable::{Hittable, Interval},
+    aov::{Aov, AovSample},
+    film::Film,
+    filter::Filter,
+    hittable::{HitRecord, Hittable, HittableList, Interval},
+    progress::{Progress, RenderObserver},
     ray::Ray,
-    units::{random_in_unit_disk, write_color, Color, Point, Vector},
+    sampler::{PixelSample, SampleRng, Sampler, Sobol},
+    tiles::{self, Tile, TileOrder},
+    time_utils::{Easing, Keyframes, Movement, PiecewiseLinear},
+    units::{random_in_unit_disk, rng_stream, Color, Point, Vector},
 };
 
 pub struct CameraBuilder {
@@ -19,32 +28,169 @@ pub struct CameraBuilder {
     lookfrom: Point,
     lookat: Point,
     vup: Vector,
+    lookfrom_keyframes: Option<Keyframes>,
+    lookat_keyframes: Option<Keyframes>,
 
     defocus_angle: f32,
     focus_dist: f32,
 
+    shutter_open: f32,
+    shutter_close: f32,
+    shutter_curve: ShutterCurve,
+    frames: usize,
+
     samples_per_pixel: usize,
     max_depth: usize,
     background: Color,
+    seed: u64,
+    sampler: Box<dyn Sampler>,
+    filter: Filter,
+    aovs: Vec<Aov>,
+
+    tile_size: usize,
+    tile_order: TileOrder,
+    adaptive: Option<AdaptiveSampling>,
+}
+
+// How far open the shutter is over the exposure, which decides how the times of camera rays are
+// distributed between opening and closing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ShutterCurve {
+    // fully open for the whole interval
+    #[default]
+    Box,
+    // opens linearly until the middle of the interval, then closes again
+    Triangle,
+    // spends `ramp` of the interval opening and as much closing, between 0 and 0.5
+    Trapezoid(f32),
 }
 
 pub struct Camera {
     image_width: usize,
     image_height: usize,
 
+    lens: Lens,
+    view: View,
+    // where the camera is and what it looks at over time, if it moves at all
+    path: Option<(Keyframes, Keyframes)>,
+
+    defocus_angle: f32,
+
+    shutter_open: f32,
+    shutter_close: f32,
+    shutter_curve: ShutterCurve,
+    frames: usize,
+
+    samples_per_pixel: usize,
+    max_depth: usize,
+    background: Color,
+    seed: u64,
+    sampler: Box<dyn Sampler>,
+    filter: Filter,
+    aovs: Vec<Aov>,
+
+    tile_size: usize,
+    tile_order: TileOrder,
+    adaptive: Option<AdaptiveSampling>,
+}
+
+// When a progressive render stops: once every pixel has `max_samples` samples, or once
+// `time_limit` has passed (even in the middle of a pass), whichever comes first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progressive {
+    // samples per pixel added by every pass
+    pub pass_samples: usize,
+    pub max_samples: Option<usize>,
+    pub time_limit: Option<Duration>,
+}
+
+// Keeps sampling the pixels whose relative error (see `Film::relative_error`) is above
+// `threshold`, up to `max_samples`, once they have the camera's samples per pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    pub threshold: f32,
+    pub max_samples: usize,
+}
+
+// What a single pass over the image renders.
+struct Pass {
+    index: usize,
+    frame: usize,
+    // for every pixel, with how many samples it had before the pass
+    samples: Vec<u32>,
+    first: Vec<u32>,
+    // how many samples pixels are expected to get over all passes
+    expected: u32,
+    // the frame's shutter interval
+    shutter: [f32; 2],
+}
+
+// The tile films of a pass, which are added to the image in the order of the tiles.
+#[derive(Default)]
+struct Merge {
+    pending: Vec<Option<Film>>,
+    // the first tile that isn't in the image yet
+    next: usize,
+    tiles_done: usize,
+}
+
+// Where a path hit something, with the light it picked up there and where it goes on from it.
+struct Vertex<'a> {
+    hit: HitRecord<'a>,
+    // already weighted against light sampling
+    emitted: Color,
+    // from light sampling
+    direct: Color,
+    // the next ray, its weight and the density it was picked with, unless it's a delta lobe
+    scattered: Option<(Ray, Color, Option<f32>)>,
+}
+
+impl AdaptiveSampling {
+    // how many more samples each pixel of `film` needs, up to `samples`; like
+    // `Camera::max_samples`, a cap below `min_samples` is raised to it
+    fn samples(&self, film: &Film, samples: usize, min_samples: usize) -> Vec<u32> {
+        let max_samples = self.max_samples.max(min_samples);
+        let mut counts = Vec::with_capacity(film.width() * film.height());
+        for y in 0..film.height() {
+            for x in 0..film.width() {
+                let n = film.sample_count(x, y) as usize;
+                let converged = n >= min_samples && film.relative_error(x, y) <= self.threshold;
+                let needed = if converged {
+                    0
+                } else {
+                    samples.min(max_samples.saturating_sub(n))
+                };
+                counts.push(needed as u32);
+            }
+        }
+
+        counts
+    }
+}
+
+// The part of the camera that doesn't depend on where it is.
+#[derive(Clone, Copy)]
+struct Lens {
+    image_width: usize,
+    image_height: usize,
+    vup: Vector,
+    viewport_width: f32,
+    viewport_height: f32,
+    focus_dist: f32,
+    defocus_radius: f32,
+}
+
+// The viewport and lens placed in the world.
+#[derive(Clone, Copy)]
+struct View {
     center: Point,
 
     pixel_00_loc: Point,
     pixel_delta_u: Vector,
     pixel_delta_v: Vector,
 
-    defocus_angle: f32,
     defocus_disk_u: Vector,
     defocus_disk_v: Vector,
-
-    samples_per_pixel: usize,
-    max_depth: usize,
-    background: Color,
 }
 
 impl Default for CameraBuilder {
@@ -57,11 +203,55 @@ impl Default for CameraBuilder {
             lookfrom: Point::new(0., 0., -1.),
             lookat: Point::new(0., 0., 0.),
             vup: Vector::new(0., 1., 0.),
+            lookfrom_keyframes: None,
+            lookat_keyframes: None,
             defocus_angle: 0.,
             focus_dist: 10.,
+            shutter_open: 0.,
+            shutter_close: 1.,
+            shutter_curve: ShutterCurve::Box,
+            frames: 1,
             samples_per_pixel: 10,
             max_depth: 10,
             background: Color::ZERO,
+            seed: 0,
+            sampler: Box::new(Sobol),
+            filter: Filter::default(),
+            aovs: Vec::new(),
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            adaptive: None,
+        }
+    }
+}
+
+impl ShutterCurve {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Self::Box),
+            "triangle" => Some(Self::Triangle),
+            _ => None,
+        }
+    }
+
+    // maps a uniform `u` in [0, 1) to a time in [0, 1) distributed like the curve
+    pub fn sample(self, u: f32) -> f32 {
+        let ramp = match self {
+            Self::Box => return u,
+            Self::Triangle => 0.5,
+            Self::Trapezoid(ramp) => ramp,
+        };
+
+        // invert the integral of a curve with height `h` while fully open, which is a parabola on
+        // the ramps and linear in between
+        let h = 1. / (1. - ramp);
+        let ramp_area = h * ramp / 2.;
+        if u < ramp_area {
+            (2. * ramp * u / h).sqrt()
+        } else if u > 1. - ramp_area {
+            1. - (2. * ramp * (1. - u) / h).sqrt()
+        } else {
+            u / h + ramp / 2.
         }
     }
 }
@@ -89,11 +279,25 @@ impl CameraBuilder {
 
     pub fn with_lookfrom(&mut self, lookfrom: Point) -> &mut Self {
         self.lookfrom = lookfrom;
+        self.lookfrom_keyframes = None;
         self
     }
 
     pub fn with_lookat(&mut self, lookat: Point) -> &mut Self {
         self.lookat = lookat;
+        self.lookat_keyframes = None;
+        self
+    }
+
+    // moves the camera along `keyframes` instead of keeping it at `lookfrom`
+    pub fn with_lookfrom_keyframes(&mut self, keyframes: Keyframes) -> &mut Self {
+        self.lookfrom_keyframes = Some(keyframes);
+        self
+    }
+
+    // turns the camera to follow `keyframes` instead of looking at `lookat`
+    pub fn with_lookat_keyframes(&mut self, keyframes: Keyframes) -> &mut Self {
+        self.lookat_keyframes = Some(keyframes);
         self
     }
 
@@ -112,6 +316,35 @@ impl CameraBuilder {
         self
     }
 
+    // the part of every frame the image is exposed for, as fractions of the frame's duration
+    pub fn with_shutter(&mut self, open: f32, close: f32) -> &mut Self {
+        assert!(
+            (0. ..=close).contains(&open) && close <= 1.,
+            "The shutter has to open and close within [0, 1]!"
+        );
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn with_shutter_curve(&mut self, shutter_curve: ShutterCurve) -> &mut Self {
+        if let ShutterCurve::Trapezoid(ramp) = shutter_curve {
+            assert!(
+                (0. ..=0.5).contains(&ramp),
+                "The shutter can't take more than half the exposure to open!"
+            );
+        }
+        self.shutter_curve = shutter_curve;
+        self
+    }
+
+    // splits the scene's [0, 1] time range into `frames` animation frames of equal length
+    pub fn with_frames(&mut self, frames: usize) -> &mut Self {
+        assert!(frames > 0, "An animation needs at least one frame!");
+        self.frames = frames;
+        self
+    }
+
     pub fn with_samples_per_pixel(&mut self, samples_per_pixel: usize) -> &mut Self {
         self.samples_per_pixel = samples_per_pixel;
         self
@@ -127,6 +360,51 @@ impl CameraBuilder {
         self
     }
 
+    pub fn with_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_sampler(&mut self, sampler: Box<dyn Sampler>) -> &mut Self {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn with_filter(&mut self, filter: Filter) -> &mut Self {
+        assert!(
+            filter.radius() >= 0.5,
+            "Filters need a radius of at least half a pixel to reach every sample!"
+        );
+        self.filter = filter;
+        self
+    }
+
+    // the AOVs to render along with the image, see `Film::aov_pixels`
+    pub fn with_aovs(&mut self, aovs: &[Aov]) -> &mut Self {
+        self.aovs = aovs.to_vec();
+        self
+    }
+
+    pub fn with_tile_size(&mut self, tile_size: usize) -> &mut Self {
+        assert!(tile_size > 0, "Tiles need to be at least one pixel wide!");
+        self.tile_size = tile_size;
+        self
+    }
+
+    pub fn with_tile_order(&mut self, tile_order: TileOrder) -> &mut Self {
+        self.tile_order = tile_order;
+        self
+    }
+
+    pub fn with_adaptive_sampling(&mut self, adaptive: AdaptiveSampling) -> &mut Self {
+        assert!(
+            adaptive.threshold > 0.,
+            "Adaptive sampling needs a positive error threshold!"
+        );
+        self.adaptive = Some(adaptive);
+        self
+    }
+
     pub fn build(self) -> Camera {
         let CameraBuilder {
             aspect_ratio,
@@ -136,11 +414,24 @@ impl CameraBuilder {
             lookfrom,
             lookat,
             vup,
+            lookfrom_keyframes,
+            lookat_keyframes,
             defocus_angle,
             focus_dist,
+            shutter_open,
+            shutter_close,
+            shutter_curve,
+            frames,
             samples_per_pixel,
             max_depth,
             background,
+            seed,
+            sampler,
+            filter,
+            aovs,
+            tile_size,
+            tile_order,
+            adaptive,
         } = self;
 
         let (image_width, image_height) = match (image_width, image_height) {
@@ -156,103 +447,383 @@ impl CameraBuilder {
             (Some(image_width), Some(image_height)) => (image_width, image_height),
         };
 
-        let center: Point = lookfrom;
-
         let theta = vfov.to_radians();
         let h = (theta / 2.).tan();
         let viewport_height = focus_dist * 2. * h;
         let viewport_width = viewport_height * image_width as f32 / image_height as f32;
 
+        let lens = Lens {
+            image_width,
+            image_height,
+            vup,
+            viewport_width,
+            viewport_height,
+            focus_dist,
+            defocus_radius: focus_dist * (defocus_angle / 2.).to_radians().tan(),
+        };
+
+        // a camera with only one of its ends keyframed holds the other one still
+        let path = match (lookfrom_keyframes, lookat_keyframes) {
+            (None, None) => None,
+            (lookfrom_keyframes, lookat_keyframes) => Some((
+                lookfrom_keyframes.unwrap_or_else(|| still(lookfrom)),
+                lookat_keyframes.unwrap_or_else(|| still(lookat)),
+            )),
+        };
+
+        let view = match &path {
+            Some((lookfrom, lookat)) => lens.view(
+                PiecewiseLinear::get_at_time(lookfrom, shutter_open),
+                PiecewiseLinear::get_at_time(lookat, shutter_open),
+            ),
+            None => lens.view(lookfrom, lookat),
+        };
+
+        Camera {
+            image_width,
+            image_height,
+            lens,
+            view,
+            path,
+            defocus_angle,
+            shutter_open,
+            shutter_close,
+            shutter_curve,
+            frames,
+            samples_per_pixel,
+            max_depth,
+            background,
+            seed,
+            sampler,
+            filter,
+            aovs,
+            tile_size,
+            tile_order,
+            adaptive,
+        }
+    }
+}
+
+// How the dimensions of a sample are used: the position in the pixel, on the lens and the time
+// come first, then every bounce gets the same number. A bounce gives the material the first few
+// and the rest go to picking a light and a point on it, which starts at an odd dimension so the
+// point gets a whole pair.
+const CAMERA_DIMENSIONS: u32 = 6;
+const BOUNCE_DIMENSIONS: u32 = 8;
+const MATERIAL_DIMENSIONS: u32 = 5;
+
+fn still(point: Point) -> Keyframes {
+    Keyframes::new(vec![(0., point)], Easing::Linear)
+}
+
+impl Lens {
+    fn view(&self, lookfrom: Point, lookat: Point) -> View {
+        let center: Point = lookfrom;
+
         // basis vectors for the camera coordinate system
         let w = (lookfrom - lookat).normalize();
-        let u = vup.cross(w).normalize();
+        let u = self.vup.cross(w).normalize();
         let v = w.cross(u);
         assert!(v.is_normalized());
 
         // vectors framing the viewport
-        let viewport_u = u * viewport_width;
-        let viewport_v = v * -viewport_height;
+        let viewport_u = u * self.viewport_width;
+        let viewport_v = v * -self.viewport_height;
 
         // vectors between the center of each pixel
-        let pixel_delta_u = viewport_u / image_width as f32;
-        let pixel_delta_v = viewport_v / image_height as f32;
+        let pixel_delta_u = viewport_u / self.image_width as f32;
+        let pixel_delta_v = viewport_v / self.image_height as f32;
 
         // location of the upper left pixel
-        let viewport_upper_left = center - (w * focus_dist) - (viewport_u + viewport_v) / 2.;
+        let viewport_upper_left = center - (w * self.focus_dist) - (viewport_u + viewport_v) / 2.;
         let pixel_00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) / 2.;
 
-        let defocus_radius = focus_dist * (defocus_angle / 2.).to_radians().tan();
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
-
-        Camera {
-            image_width,
-            image_height,
+        View {
             center,
             pixel_00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            defocus_angle,
-            defocus_disk_u,
-            defocus_disk_v,
-            samples_per_pixel,
-            max_depth,
-            background,
+            defocus_disk_u: u * self.defocus_radius,
+            defocus_disk_v: v * self.defocus_radius,
         }
     }
 }
 
 impl Camera {
-    pub fn render(&self, world: &dyn Hittable) {
-        let mut image = vec![Color::ZERO; self.image_height * self.image_width];
-        image
-            .par_chunks_mut(self.image_width)
-            .progress()
-            .enumerate()
-            .for_each(|(j, row)| {
-                for (i, pixel) in row.iter_mut().enumerate() {
-                    let mut color = Color::ZERO;
-                    for _ in 0..self.samples_per_pixel {
-                        let ray = self.get_ray(i, j);
-
-                        color += Camera::ray_color(&ray, world, self.background, self.max_depth);
-                    }
+    pub fn image_width(&self) -> usize {
+        self.image_width
+    }
+
+    pub fn image_height(&self) -> usize {
+        self.image_height
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    // the most samples a pixel can get, which is more than `samples_per_pixel` with adaptive
+    // sampling
+    pub fn max_samples(&self) -> usize {
+        match self.adaptive {
+            Some(adaptive) => adaptive.max_samples.max(self.samples_per_pixel),
+            None => self.samples_per_pixel,
+        }
+    }
+
+    pub fn render(&self, world: &dyn Hittable, lights: &[&dyn Hittable]) -> Film {
+        self.render_frame(world, lights, 0, &())
+    }
+
+    // `lights` are the emitters sampled directly at every diffuse bounce; with none given every
+    // path has to find the lights on its own
+    //
+    // `frame` counts from 0 and is exposed for its part of the shutter interval, so that moving
+    // objects and cameras are evaluated at that frame's times
+    pub fn render_frame(
+        &self,
+        world: &dyn Hittable,
+        lights: &[&dyn Hittable],
+        frame: usize,
+        observer: &dyn RenderObserver,
+    ) -> Film {
+        // with adaptive sampling, the pixels that aren't done yet get more passes
+        let progressive = Progressive {
+            pass_samples: self.samples_per_pixel,
+            max_samples: Some(self.max_samples()),
+            time_limit: None,
+        };
+        self.render_progressive(world, lights, frame, progressive, observer)
+    }
+
+    // like `render_frame`, but in passes over the whole image that each add a few samples to
+    // every pixel, until one of the limits in `progressive` is reached
+    pub fn render_progressive(
+        &self,
+        world: &dyn Hittable,
+        lights: &[&dyn Hittable],
+        frame: usize,
+        progressive: Progressive,
+        observer: &dyn RenderObserver,
+    ) -> Film {
+        assert!(
+            frame < self.frames,
+            "There are only {} frames!",
+            self.frames
+        );
+        assert!(
+            progressive.max_samples.is_some() || progressive.time_limit.is_some(),
+            "A progressive render needs a sample count or a time limit to stop at!"
+        );
+        let deadline = progressive.time_limit.map(|limit| Instant::now() + limit);
+        let out_of_time = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
 
-                    color /= self.samples_per_pixel as f32;
+        let lights = HittableList::from_vec(lights.to_vec());
+        let shutter = [self.shutter_open, self.shutter_close]
+            .map(|t| (frame as f32 + t) / self.frames as f32);
 
-                    *pixel = color
+        let tiles = tiles::tiles(
+            self.image_width,
+            self.image_height,
+            self.tile_size,
+            self.tile_order,
+        );
+        observer.started(&tiles);
+
+        // the film so far, and the tiles of the current pass that are done but not in it yet
+        let film = Film::with_aovs(self.image_width, self.image_height, &self.aovs);
+        let state = Mutex::new((film, Merge::default()));
+        let mut samples_done = 0;
+        for pass in 0.. {
+            let pass_samples = match progressive.max_samples {
+                Some(max_samples) => progressive.pass_samples.min(max_samples - samples_done),
+                None => progressive.pass_samples,
+            };
+            if pass_samples == 0 || out_of_time() {
+                break;
+            }
+
+            let (samples, first) = {
+                let (film, merge) = &mut *state.lock().unwrap();
+                *merge = Merge {
+                    pending: (0..tiles.len()).map(|_| None).collect(),
+                    ..Merge::default()
+                };
+                let samples = match self.adaptive {
+                    Some(adaptive) => adaptive.samples(film, pass_samples, self.samples_per_pixel),
+                    None => vec![pass_samples as u32; film.width() * film.height()],
+                };
+                (samples, film.sample_counts().to_vec())
+            };
+            if samples.iter().all(|&n| n == 0) {
+                break;
+            }
+            let pass = Pass {
+                index: pass,
+                frame,
+                samples,
+                first,
+                expected: progressive.max_samples.unwrap_or(self.samples_per_pixel) as u32,
+                shutter,
+            };
+
+            // tiles overlap by their margins, so they are added in a fixed order to keep floating
+            // point sums from depending on which thread finishes first
+            let merge_ready = |film: &mut Film, merge: &mut Merge, all: bool| {
+                while merge.next < tiles.len() {
+                    let tile = &tiles[merge.next];
+                    match merge.pending[merge.next].take() {
+                        Some(tile_film) => {
+                            let margin = self.tile_margin() as isize;
+                            film.add_film(
+                                tile.x as isize - margin,
+                                tile.y as isize - margin,
+                                &tile_film,
+                            );
+                            merge.tiles_done += 1;
+                            let progress = Progress {
+                                pass: pass.index,
+                                tiles_done: merge.tiles_done,
+                                tiles_total: tiles.len(),
+                            };
+                            observer.tile_finished(tile, progress, film);
+                        }
+                        None if !all => break,
+                        None => {}
+                    }
+                    merge.next += 1;
                 }
-            });
+            };
+
+            // bridging hands the tiles to the threads one at a time, in order
+            tiles
+                .iter()
+                .enumerate()
+                .par_bridge()
+                .for_each(|(idx, tile)| {
+                    // once time is up, the rest of the pass is skipped
+                    if out_of_time() {
+                        return;
+                    }
+                    let tile_film = self.render_tile(world, &lights, &pass, tile);
 
-        let stdout = std::io::stdout().lock();
-        let mut stdout = BufWriter::new(stdout);
+                    let (film, merge) = &mut *state.lock().unwrap();
+                    merge.pending[idx] = Some(tile_film);
+                    merge_ready(film, merge, false);
+                });
 
-        write!(
-            stdout,
-            "P3\n{} {}\n255\n",
-            self.image_width, self.image_height
-        )
-        .unwrap();
+            // only tiles skipped for the time limit can still be missing
+            let (film, merge) = &mut *state.lock().unwrap();
+            merge_ready(film, merge, true);
 
-        for pixel in image {
-            write_color(&mut stdout, pixel);
+            samples_done += pass_samples;
+            observer.pass_finished(pass.index, film);
         }
 
-        stdout.flush().unwrap();
-        eprintln!("Done.");
+        let (film, _) = state.into_inner().unwrap();
+        observer.finished(&film);
+        film
+    }
+
+    // how far samples near the edges of a tile spread into its neighbours
+    fn tile_margin(&self) -> usize {
+        (self.filter.radius() - 0.5).ceil() as usize
     }
 
-    fn get_ray(&self, i: usize, j: usize) -> Ray {
+    fn render_tile(
+        &self,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        pass: &Pass,
+        tile: &Tile,
+    ) -> Film {
+        let pixels = self.image_width * self.image_height;
+
+        let margin = self.tile_margin();
+        let (width, height) = (tile.width + 2 * margin, tile.height + 2 * margin);
+        let mut film = Film::with_aovs(width, height, &self.aovs);
+        for y in 0..tile.height {
+            for x in 0..tile.width {
+                let (i, j) = (tile.x + x, tile.y + y);
+                // seeding per pixel keeps the result independent of how tiles are scheduled, and
+                // every frame and pass gets its own noise
+                let stream = (pass.index * self.frames + pass.frame) * pixels;
+                let mut fallback =
+                    rng_stream(self.seed, (stream + j * self.image_width + i) as u64);
+
+                let idx = j * self.image_width + i;
+                for index in pass.first[idx]..pass.first[idx] + pass.samples[idx] {
+                    let sample = PixelSample {
+                        pixel: [i as u32, j as u32],
+                        index,
+                        samples: pass.expected,
+                        seed: self.seed ^ (pass.frame as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15),
+                    };
+                    let mut rng = SampleRng::new(&*self.sampler, sample, &mut fallback);
+
+                    rng.start_dimensions(0, 2);
+                    let offset = [rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5];
+                    let ray = self.get_ray(i, j, offset, pass.shutter, &mut rng);
+                    let (color, aovs) = if self.aovs.is_empty() {
+                        let color =
+                            self.ray_color(&ray, world, lights, self.max_depth, None, &mut rng);
+                        (color, AovSample::default())
+                    } else {
+                        let aovs = self.ray_aovs(&ray, world, lights, &mut rng);
+                        (
+                            aovs[Aov::Emission] + aovs[Aov::Direct] + aovs[Aov::Indirect],
+                            aovs,
+                        )
+                    };
+                    film.add_filtered_sample(
+                        x + margin,
+                        y + margin,
+                        offset,
+                        color,
+                        &aovs,
+                        self.filter,
+                    );
+                }
+            }
+        }
+
+        film
+    }
+
+    // the ray through `[px, py]` away from the center of pixel (i, j)
+    fn get_ray(
+        &self,
+        i: usize,
+        j: usize,
+        [px, py]: [f32; 2],
+        [open, close]: [f32; 2],
+        rng: &mut SampleRng,
+    ) -> Ray {
+        rng.start_dimensions(2, 2);
+        let lens_sample = (self.defocus_angle > 0.).then(|| random_in_unit_disk(rng));
+        rng.start_dimensions(4, 1);
+        let time = open + (close - open) * self.shutter_curve.sample(rng.gen());
+
+        let view = match &self.path {
+            Some((lookfrom, lookat)) => self.lens.view(
+                PiecewiseLinear::get_at_time(lookfrom, time),
+                PiecewiseLinear::get_at_time(lookat, time),
+            ),
+            None => self.view,
+        };
+
         let pixel_center =
-            self.pixel_00_loc + self.pixel_delta_u * i as f32 + self.pixel_delta_v * j as f32;
-        let pixel_sample = pixel_center + self.pixel_sample_square();
-        let origin = if self.defocus_angle <= 0. {
-            self.center
-        } else {
-            self.defocus_disk_sample()
+            view.pixel_00_loc + view.pixel_delta_u * i as f32 + view.pixel_delta_v * j as f32;
+        let pixel_sample = pixel_center + (view.pixel_delta_u * px) + (view.pixel_delta_v * py);
+        let origin = match lens_sample {
+            Some(p) => view.center + view.defocus_disk_u * p.x + view.defocus_disk_v * p.y,
+            None => view.center,
         };
         let direct = pixel_sample - origin;
-        let time = random();
 
         Ray {
             origin,
@@ -261,38 +832,329 @@ impl Camera {
         }
     }
 
-    fn defocus_disk_sample(&self) -> Point {
-        let p = random_in_unit_disk();
-        self.center + self.defocus_disk_u * p.x + self.defocus_disk_v * p.y
+    // `bsdf_pdf` is the density with which the previous bounce picked `ray`, or `None` if that
+    // bounce was specular (or `ray` is a camera ray) and light sampling could not have found it
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        depth: usize,
+        bsdf_pdf: Option<f32>,
+        rng: &mut SampleRng,
+    ) -> Color {
+        if depth == 0 {
+            return Color::ZERO;
+        }
+
+        let Some(vertex) = self.vertex(ray, world, lights, depth, bsdf_pdf, rng) else {
+            return self.background;
+        };
+
+        let mut color = vertex.emitted + vertex.direct;
+        if let Some((scattered, weight, next_pdf)) = vertex.scattered {
+            color += self.ray_color(&scattered, world, lights, depth - 1, next_pdf, rng) * weight;
+        }
+
+        color
     }
 
-    fn ray_color(ray: &Ray, world: &dyn Hittable, background: Color, depth: usize) -> Color {
+    // like `ray_color` for a camera ray, split up into the lighting AOVs, along with the other
+    // AOVs of the first hit
+    fn ray_aovs(
+        &self,
+        ray: &Ray,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        rng: &mut SampleRng,
+    ) -> AovSample {
+        let mut aovs = AovSample::default();
+        let depth = self.max_depth;
         if depth == 0 {
-            return Color::ZERO;
+            return aovs;
         }
 
-        match world.hit(ray, Interval::<f32>::POSITIVE) {
-            None => background,
-            Some(hit) => {
-                let hit_info = hit.mat.hit_info(ray, &hit);
-                let mut color = Color::ZERO;
+        let Some(first) = self.vertex(ray, world, lights, depth, None, rng) else {
+            aovs[Aov::Emission] = self.background;
+            return aovs;
+        };
+
+        let hit = &first.hit;
+        aovs[Aov::Depth] = Color::splat(hit.t * ray.direct.length());
+        aovs[Aov::Normal] = Color::from_array(*hit.normal.as_array());
+        aovs[Aov::Albedo] = hit.mat.albedo(hit);
+        aovs[Aov::Uv] = Color::new(hit.uv.x, hit.uv.y, 0.);
+        aovs[Aov::ObjectId] = Color::splat(hit.object_id as f32);
+        aovs[Aov::MaterialId] = Color::splat(hit.mat.id() as f32);
+
+        aovs[Aov::Emission] = first.emitted;
+        aovs[Aov::Direct] = first.direct;
+        let Some((scattered, weight, next_pdf)) = first.scattered else {
+            return aovs;
+        };
+        if depth == 1 {
+            return aovs;
+        }
 
-                if let Some((attenuation, scattered)) = hit_info.scatter {
-                    color +=
-                        Self::ray_color(&scattered, world, background, depth - 1) * attenuation;
+        // what the second hit gives off reached the first one directly, the rest didn't
+        match self.vertex(&scattered, world, lights, depth - 1, next_pdf, rng) {
+            Some(second) => {
+                aovs[Aov::Direct] += second.emitted * weight;
+                let mut indirect = second.direct;
+                if let Some((scattered, second_weight, next_pdf)) = second.scattered {
+                    indirect += self.ray_color(&scattered, world, lights, depth - 2, next_pdf, rng)
+                        * second_weight;
                 }
-                if let Some(emit) = hit_info.emit {
-                    color += emit;
+                aovs[Aov::Indirect] = indirect * weight;
+            }
+            None => aovs[Aov::Direct] += self.background * weight,
+        }
+
+        aovs
+    }
+
+    // what happens where `ray` hits the world first, if it does
+    fn vertex<'w>(
+        &self,
+        ray: &Ray,
+        world: &'w dyn Hittable,
+        lights: &HittableList,
+        depth: usize,
+        bsdf_pdf: Option<f32>,
+        rng: &mut SampleRng,
+    ) -> Option<Vertex<'w>> {
+        let hit = world.hit(ray, Interval::<f32>::POSITIVE)?;
+
+        let mut emitted = hit.mat.emitted(ray, &hit);
+        if emitted.max_element() > 0. {
+            let weight = match bsdf_pdf {
+                Some(bsdf_pdf) if !lights.is_empty() => {
+                    let light_pdf = lights.pdf_value(ray.origin, ray.direct, ray.time);
+                    power_heuristic(bsdf_pdf, light_pdf)
                 }
-                color
+                _ => 1.,
+            };
+            emitted *= weight;
+        }
+
+        // every bounce draws from its own dimensions, whatever the bounces before it took
+        let dimensions = CAMERA_DIMENSIONS + (self.max_depth - depth) as u32 * BOUNCE_DIMENSIONS;
+        rng.start_dimensions(dimensions, MATERIAL_DIMENSIONS);
+        let mut direct = Color::ZERO;
+        let scattered = hit.mat.sample(ray, &hit, rng).map(|sample| {
+            // the last bounce can't reach a light through the material either, so sampling lights
+            // there as well would make the result depend on whether lights are given
+            if !sample.delta && !lights.is_empty() && depth > 1 {
+                rng.start_dimensions(
+                    dimensions + MATERIAL_DIMENSIONS,
+                    BOUNCE_DIMENSIONS - MATERIAL_DIMENSIONS,
+                );
+                direct = Self::sample_light(ray, &hit, world, lights, rng);
+            }
+
+            let scattered = Ray {
+                origin: hit.p,
+                direct: sample.direction,
+                time: ray.time,
+            };
+            (
+                scattered,
+                sample.weight,
+                (!sample.delta).then_some(sample.pdf),
+            )
+        });
+
+        Some(Vertex {
+            hit,
+            emitted,
+            direct,
+            scattered,
+        })
+    }
+
+    fn sample_light(
+        ray: &Ray,
+        hit: &HitRecord,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        rng: &mut SampleRng,
+    ) -> Color {
+        let direct = lights.random(hit.p, ray.time, rng);
+        let light_pdf = lights.pdf_value(hit.p, direct, ray.time);
+        if light_pdf <= 0. {
+            return Color::ZERO;
+        }
+
+        let f = hit.mat.eval(ray, hit, direct);
+        if f.max_element() <= 0. {
+            return Color::ZERO;
+        }
+
+        let shadow = Ray {
+            origin: hit.p,
+            direct,
+            time: ray.time,
+        };
+        let Some(light_hit) = world.hit(&shadow, Interval::<f32>::POSITIVE) else {
+            return Color::ZERO;
+        };
+
+        let emitted = light_hit.mat.emitted(&shadow, &light_hit);
+        let weight = power_heuristic(light_pdf, hit.mat.pdf(ray, hit, direct));
+
+        f * emitted * (weight / light_pdf)
+    }
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    a / (a + b)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::Global;
+
+    use crate::{
+        hittable::{quad::Quad, Sphere},
+        material::{DiffuseLight, Lambertian, Material},
+        time_utils::Unchanging,
+    };
+
+    use super::*;
+
+    fn leak<T>(x: T) -> &'static T {
+        Box::leak(Box::new(x))
+    }
+
+    // a small box with a light in its ceiling and a sphere on the floor
+    fn scene() -> (&'static dyn Hittable, &'static dyn Hittable) {
+        let white: &dyn Material = leak(Lambertian::new_with_color(Color::splat(0.73), Global));
+        let red: &dyn Material = leak(Lambertian::new_with_color(
+            Color::new(0.65, 0.05, 0.05),
+            Global,
+        ));
+        let light: &dyn Material = leak(DiffuseLight::new_with_color(Color::splat(15.), Global));
+
+        let light = leak(Quad::new(
+            Point::new(-0.3, 0.99, -0.3),
+            Vector::new(0.6, 0., 0.),
+            Vector::new(0., 0., 0.6),
+            light,
+        ));
+        let mut objects: Vec<&dyn Hittable> =
+            Quad::new_box(Point::new(-1., -1., -1.), Point::new(1., 1., 1.), white)
+                .into_iter()
+                .map(|face| leak(face) as &dyn Hittable)
+                .collect();
+        objects.push(leak(Sphere::<Unchanging>::new(
+            Point::new(0.2, -0.6, 0.),
+            0.4,
+            red,
+        )));
+        objects.push(light);
+
+        (leak(HittableList::from_vec(objects)), light)
+    }
+
+    fn camera(filter: Filter) -> CameraBuilder {
+        let mut camera = CameraBuilder::default();
+        camera
+            .with_aspect_ratio(1.)
+            .with_image_width(24)
+            .with_vfov(60.)
+            .with_lookfrom(Point::new(0., 0., 0.9))
+            .with_lookat(Point::new(0., 0., 0.))
+            .with_defocus_angle(0.)
+            .with_samples_per_pixel(4)
+            .with_max_depth(5)
+            .with_seed(7)
+            .with_filter(filter)
+            .with_tile_size(4);
+        camera
+    }
+
+    fn render(filter: Filter, threads: usize) -> Vec<Color> {
+        let (world, light) = scene();
+        let camera = camera(filter).build();
+
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| camera.render(world, &[light]).pixels())
+    }
+
+    fn assert_reproducible(filter: Filter) {
+        let bits = |pixels: Vec<Color>| -> Vec<[u32; 3]> {
+            pixels
+                .iter()
+                .map(|c| c.as_array().map(f32::to_bits))
+                .collect()
+        };
+
+        let expected = bits(render(filter, 1));
+        for threads in [2, 8] {
+            for _ in 0..3 {
+                assert!(
+                    bits(render(filter, threads)) == expected,
+                    "{filter:?} differs with {threads} threads"
+                );
             }
         }
     }
 
-    fn pixel_sample_square(&self) -> Vector {
-        let px = random::<f32>() - 0.5;
-        let py = random::<f32>() - 0.5;
+    #[test]
+    fn box_filter_is_reproducible_across_threads() {
+        assert_reproducible(Filter::default());
+    }
+
+    #[test]
+    fn wide_filters_are_reproducible_across_threads() {
+        assert_reproducible(Filter::from_name("mitchell").unwrap());
+        assert_reproducible(Filter::from_name("lanczos").unwrap());
+    }
+
+    #[test]
+    fn adaptive_cap_below_min_samples_is_raised() {
+        let adaptive = AdaptiveSampling {
+            threshold: 0.01,
+            max_samples: 16,
+        };
+        let mut film = Film::new(2, 1);
+        assert_eq!(adaptive.samples(&film, 64, 64), [64, 64]);
 
-        (self.pixel_delta_u * px) + (self.pixel_delta_v * py)
+        // a noisy pixel keeps sampling up to the raised cap, and not past it
+        for i in 0..48 {
+            film.add_sample(0, 0, Color::splat((i % 2) as f32));
+        }
+        assert_eq!(adaptive.samples(&film, 64, 64), [16, 64]);
+        for i in 0..16 {
+            film.add_sample(0, 0, Color::splat((i % 2) as f32));
+        }
+        assert_eq!(adaptive.samples(&film, 64, 64)[0], 0);
+    }
+
+    #[test]
+    fn lighting_aovs_add_up_to_the_render() {
+        let (world, light) = scene();
+        let lighting = [Aov::Direct, Aov::Indirect, Aov::Emission];
+        for filter in [Filter::default(), Filter::from_name("tent").unwrap()] {
+            let mut camera = camera(filter);
+            camera.with_aovs(&lighting);
+            let film = camera.build().render(world, &[light]);
+
+            let layers = lighting.map(|aov| film.aov_pixels(aov).unwrap());
+            for (i, pixel) in film.pixels().into_iter().enumerate() {
+                let sum = layers[0][i] + layers[1][i] + layers[2][i];
+                let error = (sum - pixel).as_array().map(f32::abs);
+                assert!(
+                    error
+                        .iter()
+                        .all(|&e| e <= 1e-4 * pixel.max_element().max(1.)),
+                    "{sum:?} != {pixel:?} with {filter:?}"
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,573 @@
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use raytracing::{
+    aov::Aov,
+    filter::Filter,
+    output::OutputFormat,
+    sampler::{self, Sampler},
+    tiles::TileOrder,
+    tonemap::{Operator, ToneMapper},
+};
+
+pub const USAGE: &str = "\
+usage: raytracing [OPTIONS] [SCENE]
+
+SCENE is the name of a built-in scene or the path to a .json scene file
+(default: cornell-box). Use --list-scenes to see the built-in scenes.
+
+options:
+  -W, --width N        image width in pixels
+  -H, --height N       image height in pixels
+  -s, --spp N          samples per pixel
+  -d, --max-depth N    maximum number of bounces per path
+      --seed N         seed for all random sampling
+  -o, --output PATH    where to write the image (default: image.png, or frame.png
+                       numbered as frame_0001.png and so on for animations)
+  -f, --format FORMAT  ppm, png, jpg, hdr or exr (default: from the output extension)
+      --exposure EV    brighten (or darken, if negative) by EV stops
+      --tone-map OP    how ppm, png and jpg fit bright colors into the image: clamp,
+                       reinhard, aces or agx (default: clamp)
+      --dither         add noise before rounding to 8 bits, against banding
+      --frames N       render an animation of N frames over the scene's time
+      --frame-range A-B
+                       only render frames A to B of the animation, counting from 1
+      --progressive    render in passes over the whole image until it has
+                       the samples per pixel or the time limit is up
+      --pass-spp N     samples per pixel added by each pass (default: 1)
+      --time-limit S   stop a progressive render after S seconds; unless --spp
+                       is given as well, it runs until then
+      --save-passes    write the image after every pass, to watch it refine
+      --adaptive T     keep sampling pixels whose relative error is above T
+                       (e.g. 0.02) after --spp samples
+      --max-spp N      the most samples adaptive sampling takes (default: 1024)
+      --sample-map PATH
+                       also write how many samples each pixel got
+      --aovs LIST      also render these buffers for compositing, comma separated:
+                       depth, normal, albedo, uv, object_id, material_id, direct,
+                       indirect, emission, or all. exr output gets them as layers,
+                       other formats as images like image_depth.png
+      --sampler NAME   where sample positions come from: independent, stratified,
+                       halton, sobol or blue_noise (default: sobol)
+      --filter NAME    how samples are shared between neighbouring pixels: box,
+                       tent, gaussian, mitchell or lanczos (default: box)
+      --filter-radius R
+                       the radius of --filter in pixels, at least 0.5
+      --tile-size N    render in tiles of N by N pixels (default: 32)
+      --tile-order ORDER
+                       hand out tiles in scanline, spiral or hilbert order
+                       (default: spiral)
+  -j, --threads N      number of render threads (default: one per core)
+      --list-scenes    list the built-in scenes and exit
+  -h, --help           show this message and exit";
+
+pub enum Command {
+    Render(Box<Args>),
+    ListScenes,
+    Help,
+}
+
+pub enum SceneSource {
+    Builtin(&'static str),
+    File(PathBuf),
+}
+
+pub struct Args {
+    pub scene: SceneSource,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub seed: Option<u64>,
+    pub output: Option<PathBuf>,
+    pub format: OutputFormat,
+    pub tone_mapper: ToneMapper,
+    pub frames: Option<usize>,
+    pub frame_range: Option<(usize, usize)>,
+    pub progressive: bool,
+    pub pass_samples: Option<usize>,
+    pub time_limit: Option<Duration>,
+    pub save_passes: bool,
+    pub adaptive: Option<f32>,
+    pub max_samples: Option<usize>,
+    pub sample_map: Option<PathBuf>,
+    pub aovs: Vec<Aov>,
+    pub sampler: Option<Box<dyn Sampler>>,
+    pub filter: Option<Filter>,
+    pub tile_size: Option<usize>,
+    pub tile_order: Option<TileOrder>,
+    pub threads: Option<usize>,
+}
+
+pub fn parse<I: Iterator<Item = String>>(
+    mut args: I,
+    scenes: &[&'static str],
+) -> Result<Command, String> {
+    let mut scene = None;
+    let mut width = None;
+    let mut height = None;
+    let mut samples_per_pixel = None;
+    let mut max_depth = None;
+    let mut seed = None;
+    let mut output = None;
+    let mut format = None;
+    let mut tone_mapper = ToneMapper::default();
+    let mut threads = None;
+    let mut frames = None;
+    let mut frame_range = None;
+    let mut progressive = false;
+    let mut pass_samples = None;
+    let mut time_limit = None;
+    let mut save_passes = false;
+    let mut adaptive = None;
+    let mut max_samples = None;
+    let mut sample_map = None;
+    let mut aovs = Vec::new();
+    let mut sampler = None;
+    let mut filter = None;
+    let mut filter_radius = None;
+    let mut tile_size = None;
+    let mut tile_order = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if scene.is_some() {
+                return Err(format!(
+                    "unexpected argument `{arg}`: only one scene can be given"
+                ));
+            }
+            scene = Some(parse_scene(&arg, scenes)?);
+            continue;
+        }
+
+        // accept both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        // switches take no value, so that `--dither=no` can't quietly turn dithering on
+        if let (
+            "-h" | "--help" | "--list-scenes" | "--progressive" | "--save-passes" | "--dither",
+            Some(value),
+        ) = (flag.as_str(), &inline_value)
+        {
+            return Err(format!(
+                "unexpected value `{value}` for {flag}, which is a switch"
+            ));
+        }
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {flag}"))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--list-scenes" => return Ok(Command::ListScenes),
+            "-W" | "--width" => width = Some(parse_positive(&flag, &value()?)?),
+            "-H" | "--height" => height = Some(parse_positive(&flag, &value()?)?),
+            "-s" | "--spp" => samples_per_pixel = Some(parse_positive(&flag, &value()?)?),
+            "-d" | "--max-depth" => max_depth = Some(parse_positive(&flag, &value()?)?),
+            "-j" | "--threads" => threads = Some(parse_positive(&flag, &value()?)?),
+            "--frames" => frames = Some(parse_positive(&flag, &value()?)?),
+            "--frame-range" => frame_range = Some(parse_frame_range(&flag, &value()?)?),
+            "--progressive" => progressive = true,
+            "--pass-spp" => pass_samples = Some(parse_positive(&flag, &value()?)?),
+            "--time-limit" => time_limit = Some(parse_duration(&flag, &value()?)?),
+            "--save-passes" => save_passes = true,
+            "--adaptive" => {
+                let threshold: f32 = parse_number(&flag, &value()?, "a positive number")?;
+                if threshold.is_nan() || threshold <= 0. {
+                    return Err(format!(
+                        "invalid value `{threshold}` for {flag}: expected a positive number"
+                    ));
+                }
+                adaptive = Some(threshold);
+            }
+            "--max-spp" => max_samples = Some(parse_positive(&flag, &value()?)?),
+            "--sample-map" => sample_map = Some(PathBuf::from(value()?)),
+            "--aovs" => aovs = parse_aovs(&flag, &value()?)?,
+            "--sampler" => {
+                let name = value()?;
+                sampler = Some(sampler::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "unknown sampler `{name}` (expected independent, stratified, halton, \
+                         sobol or blue_noise)"
+                    )
+                })?);
+            }
+            "--filter" => {
+                let name = value()?;
+                filter = Some(Filter::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "unknown filter `{name}` (expected box, tent, gaussian, mitchell or \
+                         lanczos)"
+                    )
+                })?);
+            }
+            "--filter-radius" => {
+                let radius: f32 = parse_number(&flag, &value()?, "a number of pixels")?;
+                if radius.is_nan() || radius < 0.5 {
+                    return Err(format!(
+                        "invalid value `{radius}` for {flag}: expected at least 0.5"
+                    ));
+                }
+                filter_radius = Some(radius);
+            }
+            "--tile-size" => tile_size = Some(parse_positive(&flag, &value()?)?),
+            "--tile-order" => {
+                let name = value()?;
+                tile_order = Some(TileOrder::from_name(&name).ok_or_else(|| {
+                    format!("unknown tile order `{name}` (expected scanline, spiral or hilbert)")
+                })?);
+            }
+            "--seed" => seed = Some(parse_number(&flag, &value()?, "an integer")?),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "-f" | "--format" => {
+                let name = value()?;
+                format = Some(OutputFormat::from_extension(&name).ok_or_else(|| {
+                    format!(
+                        "unknown output format `{name}` (expected one of: {})",
+                        format_names()
+                    )
+                })?);
+            }
+            "--exposure" => tone_mapper.exposure = parse_number(&flag, &value()?, "a number")?,
+            "--tone-map" => {
+                let name = value()?;
+                tone_mapper.operator = Operator::from_name(&name).ok_or_else(|| {
+                    format!("unknown tone map `{name}` (expected clamp, reinhard, aces or agx)")
+                })?;
+            }
+            "--dither" => tone_mapper.dither = true,
+            _ => return Err(format!("unknown option `{flag}`")),
+        }
+    }
+
+    let filter = match (filter, filter_radius) {
+        (filter, None) => filter,
+        (Some(filter), Some(radius)) => Some(filter.with_radius(radius)),
+        (None, Some(_)) => return Err("--filter-radius needs a --filter to apply to".to_string()),
+    };
+
+    // a time limit alone makes the render progressive too
+    if !progressive && time_limit.is_none() {
+        if pass_samples.is_some() {
+            return Err("--pass-spp needs --progressive or --time-limit".to_string());
+        }
+        if save_passes {
+            return Err("--save-passes needs --progressive or --time-limit".to_string());
+        }
+    }
+
+    if max_samples.is_some() && adaptive.is_none() {
+        return Err("--max-spp needs --adaptive".to_string());
+    }
+    if let (Some(max_samples), Some(samples_per_pixel)) = (max_samples, samples_per_pixel) {
+        if max_samples < samples_per_pixel {
+            return Err(format!(
+                "--max-spp {max_samples} is below --spp {samples_per_pixel}, which every pixel \
+                 gets before adaptive sampling starts"
+            ));
+        }
+    }
+
+    // the default output names for stills and animations are both PNGs
+    let format = match (format, &output) {
+        (Some(format), _) => format,
+        (None, None) => OutputFormat::Png,
+        (None, Some(output)) => OutputFormat::from_path(output).ok_or_else(|| {
+            format!(
+                "cannot tell the output format from `{}`; use an extension or --format with one \
+                 of: {}",
+                output.display(),
+                format_names()
+            )
+        })?,
+    };
+
+    Ok(Command::Render(Box::new(Args {
+        scene: scene.unwrap_or(SceneSource::Builtin("cornell-box")),
+        width,
+        height,
+        samples_per_pixel,
+        max_depth,
+        seed,
+        output,
+        format,
+        tone_mapper,
+        frames,
+        frame_range,
+        progressive,
+        pass_samples,
+        time_limit,
+        save_passes,
+        adaptive,
+        max_samples,
+        sample_map,
+        aovs,
+        sampler,
+        filter,
+        tile_size,
+        tile_order,
+        threads,
+    })))
+}
+
+fn parse_scene(arg: &str, scenes: &[&'static str]) -> Result<SceneSource, String> {
+    if let Some(&name) = scenes.iter().find(|&&name| name == arg) {
+        return Ok(SceneSource::Builtin(name));
+    }
+
+    let path = PathBuf::from(arg);
+    if path.extension().is_some_and(|ext| ext == "json") || path.is_file() {
+        return Ok(SceneSource::File(path));
+    }
+
+    Err(format!(
+        "unknown scene `{arg}` (built-in scenes: {}; or give the path to a .json scene file)",
+        scenes.join(", ")
+    ))
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: &str, expected: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{value}` for {flag}: expected {expected}"))
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<usize, String> {
+    match parse_number(flag, value, "a positive integer")? {
+        0 => Err(format!(
+            "invalid value `{value}` for {flag}: expected a positive integer"
+        )),
+        n => Ok(n),
+    }
+}
+
+// a positive number of seconds
+fn parse_duration(flag: &str, value: &str) -> Result<Duration, String> {
+    let seconds: f64 = parse_number(flag, value, "a number of seconds")?;
+    if !(seconds > 0. && seconds.is_finite()) {
+        return Err(format!(
+            "invalid value `{value}` for {flag}: expected a positive number of seconds"
+        ));
+    }
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+// either a single frame `A` or an inclusive range `A-B`
+fn parse_frame_range(flag: &str, value: &str) -> Result<(usize, usize), String> {
+    let (first, last) = match value.split_once('-') {
+        Some((first, last)) => (parse_positive(flag, first)?, parse_positive(flag, last)?),
+        None => {
+            let frame = parse_positive(flag, value)?;
+            (frame, frame)
+        }
+    };
+
+    if first > last {
+        return Err(format!(
+            "invalid value `{value}` for {flag}: the range ends before it starts"
+        ));
+    }
+
+    Ok((first, last))
+}
+
+// a comma separated list of AOV names, or `all`, in the order given
+fn parse_aovs(flag: &str, value: &str) -> Result<Vec<Aov>, String> {
+    if value == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+
+    let mut aovs = Vec::new();
+    for name in value.split(',').map(str::trim) {
+        let aov = Aov::from_name(name).ok_or_else(|| {
+            let names = Aov::ALL.map(Aov::name).join(", ");
+            format!("unknown AOV `{name}` for {flag} (expected all or some of: {names})")
+        })?;
+        if !aovs.contains(&aov) {
+            aovs.push(aov);
+        }
+    }
+
+    Ok(aovs)
+}
+
+fn format_names() -> String {
+    OutputFormat::ALL.map(OutputFormat::extension).join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENES: [&str; 2] = ["cornell-box", "quads"];
+
+    fn parse_args(args: &str) -> Result<Command, String> {
+        parse(args.split_whitespace().map(String::from), &SCENES)
+    }
+
+    fn render_args(args: &str) -> Box<Args> {
+        match parse_args(args) {
+            Ok(Command::Render(args)) => args,
+            Ok(_) => panic!("`{args}` doesn't render"),
+            Err(err) => panic!("`{args}` failed: {err}"),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        match parse_args(args) {
+            Ok(_) => panic!("`{args}` should have been rejected"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn defaults_and_values() {
+        let args = render_args("");
+        assert!(matches!(args.scene, SceneSource::Builtin("cornell-box")));
+        assert_eq!(args.format, OutputFormat::Png);
+        assert_eq!(args.samples_per_pixel, None);
+
+        let args = render_args(
+            "quads -W 320 --height=240 --spp 16 -d 8 --seed 3 -o out.exr --exposure -1.5 \
+             --frame-range 2-4 --aovs depth,normal,depth --filter mitchell --filter-radius 1.5",
+        );
+        assert!(matches!(args.scene, SceneSource::Builtin("quads")));
+        assert_eq!((args.width, args.height), (Some(320), Some(240)));
+        assert_eq!(
+            (args.samples_per_pixel, args.max_depth),
+            (Some(16), Some(8))
+        );
+        assert_eq!(args.seed, Some(3));
+        assert_eq!(args.format, OutputFormat::Exr);
+        assert_eq!(args.tone_mapper.exposure, -1.5);
+        assert_eq!(args.frame_range, Some((2, 4)));
+        assert_eq!(args.aovs, [Aov::Depth, Aov::Normal]);
+        assert_eq!(args.filter.map(Filter::radius), Some(1.5));
+
+        assert!(matches!(parse_args("--spp 4 --help"), Ok(Command::Help)));
+        assert!(matches!(
+            parse_args("--list-scenes"),
+            Ok(Command::ListScenes)
+        ));
+        assert!(matches!(
+            render_args("scenes/cornell_box.json").scene,
+            SceneSource::File(_)
+        ));
+    }
+
+    #[test]
+    fn unknown_flags_and_names() {
+        assert_eq!(error("--wdth 10"), "unknown option `--wdth`");
+        assert_eq!(error("-x"), "unknown option `-x`");
+        assert_eq!(
+            error("--dither=yes"),
+            "unexpected value `yes` for --dither, which is a switch"
+        );
+        assert!(error("no-such-scene").starts_with("unknown scene `no-such-scene`"));
+        assert_eq!(
+            error("quads cornell-box"),
+            "unexpected argument `cornell-box`: only one scene can be given"
+        );
+        assert!(error("--sampler random").starts_with("unknown sampler `random`"));
+        assert!(error("--filter sinc").starts_with("unknown filter `sinc`"));
+        assert!(error("--tone-map filmic").starts_with("unknown tone map `filmic`"));
+        assert!(error("--tile-order zigzag").starts_with("unknown tile order `zigzag`"));
+        assert!(error("--aovs depth,shadow").starts_with("unknown AOV `shadow` for --aovs"));
+        assert!(error("-f gif").starts_with("unknown output format `gif`"));
+        assert!(error("-o image.gif").starts_with("cannot tell the output format"));
+    }
+
+    #[test]
+    fn missing_values() {
+        for flag in [
+            "-W",
+            "--spp",
+            "--seed",
+            "-o",
+            "--aovs",
+            "--adaptive",
+            "--frame-range",
+        ] {
+            assert_eq!(error(flag), format!("missing value for {flag}"));
+        }
+    }
+
+    #[test]
+    fn non_positive_numbers() {
+        assert_eq!(
+            error("--width 0"),
+            "invalid value `0` for --width: expected a positive integer"
+        );
+        assert_eq!(
+            error("-s -4"),
+            "invalid value `-4` for -s: expected a positive integer"
+        );
+        assert_eq!(
+            error("--tile-size 2.5"),
+            "invalid value `2.5` for --tile-size: expected a positive integer"
+        );
+        assert_eq!(
+            error("--adaptive 0"),
+            "invalid value `0` for --adaptive: expected a positive number"
+        );
+        assert_eq!(
+            error("--adaptive=-0.1"),
+            "invalid value `-0.1` for --adaptive: expected a positive number"
+        );
+        assert_eq!(
+            error("--time-limit 0"),
+            "invalid value `0` for --time-limit: expected a positive number of seconds"
+        );
+        assert_eq!(
+            error("--filter box --filter-radius 0.25"),
+            "invalid value `0.25` for --filter-radius: expected at least 0.5"
+        );
+        assert_eq!(
+            error("--frame-range 0-3"),
+            "invalid value `0` for --frame-range: expected a positive integer"
+        );
+        assert_eq!(
+            error("--frame-range 5-2"),
+            "invalid value `5-2` for --frame-range: the range ends before it starts"
+        );
+        assert_eq!(
+            error("--seed -1"),
+            "invalid value `-1` for --seed: expected an integer"
+        );
+    }
+
+    #[test]
+    fn flags_that_need_other_flags() {
+        assert_eq!(
+            error("--filter-radius 2"),
+            "--filter-radius needs a --filter to apply to"
+        );
+        assert_eq!(
+            error("--pass-spp 4"),
+            "--pass-spp needs --progressive or --time-limit"
+        );
+        assert_eq!(
+            error("--save-passes"),
+            "--save-passes needs --progressive or --time-limit"
+        );
+        assert_eq!(error("--max-spp 64"), "--max-spp needs --adaptive");
+        assert_eq!(
+            error("--adaptive 0.05 --spp 64 --max-spp 16"),
+            "--max-spp 16 is below --spp 64, which every pixel gets before adaptive sampling \
+             starts"
+        );
+
+        // a time limit makes the render progressive by itself
+        let args = render_args("--time-limit 5 --pass-spp 4 --save-passes");
+        assert_eq!(args.pass_samples, Some(4));
+        assert!(args.save_passes && !args.progressive);
+        assert!(render_args("--progressive --save-passes").save_passes);
+        let args = render_args("--adaptive 0.05 --spp 16 --max-spp 16");
+        assert_eq!((args.adaptive, args.max_samples), (Some(0.05), Some(16)));
+    }
+}
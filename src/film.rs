@@ -0,0 +1,602 @@
+use std::path::Path;
+
+use crate::{
+    aov::{Aov, AovSample},
+    filter::Filter,
+    output::{self, OutputError, OutputFormat},
+    units::{luminance, Color},
+};
+
+// A framebuffer of linear radiance, accumulated as a weighted sum of the samples around each pixel.
+// Samples are counted, and their luminance tracked, in the pixel they were taken for, to estimate
+// how noisy each pixel still is. The film can also keep AOVs, see `Aov::is_lighting`.
+pub struct Film {
+    width: usize,
+    height: usize,
+    sum: Vec<Color>,
+    weights: Vec<f32>,
+    samples: Vec<u32>,
+    moments: Vec<Moments>,
+    layers: Vec<Layer>,
+}
+
+struct Layer {
+    aov: Aov,
+    // weighted sums like `Film::sum` for the lighting AOVs, the first value for IDs, and the sum
+    // of the samples taken for each pixel otherwise
+    values: Vec<Color>,
+}
+
+// Welford's running mean and sum of squared deviations of a pixel's sample luminance.
+#[derive(Clone, Copy, Default)]
+struct Moments {
+    mean: f32,
+    m2: f32,
+}
+
+// dark pixels are judged against this instead of their mean, so noise too dim to see doesn't
+// count as a large relative error
+const ERROR_FLOOR: f32 = 0.01;
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::with_aovs(width, height, &[])
+    }
+
+    pub fn with_aovs(width: usize, height: usize, aovs: &[Aov]) -> Self {
+        let layers = aovs
+            .iter()
+            .map(|&aov| Layer {
+                aov,
+                values: vec![Color::ZERO; width * height],
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            sum: vec![Color::ZERO; width * height],
+            weights: vec![0.; width * height],
+            samples: vec![0; width * height],
+            moments: vec![Moments::default(); width * height],
+            layers,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        assert!(x < self.width && y < self.height);
+        y * self.width + x
+    }
+
+    pub fn add_sample(&mut self, x: usize, y: usize, color: Color) {
+        let idx = self.index(x, y);
+        self.sum[idx] += color;
+        self.weights[idx] += 1.;
+        self.count_sample(idx, color);
+    }
+
+    // adds a sample taken `offset` away from the center of pixel (x, y), with both coordinates
+    // in [-0.5, 0.5), to every pixel within reach of `filter`; `aovs` only needs values for the
+    // AOVs this film keeps
+    pub fn add_filtered_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        offset: [f32; 2],
+        color: Color,
+        aovs: &AovSample,
+        filter: Filter,
+    ) {
+        let radius = filter.radius();
+        let position = [x as f32 + offset[0], y as f32 + offset[1]];
+        let [x_range, y_range] =
+            [(position[0], self.width), (position[1], self.height)].map(|(p, size)| {
+                let first = (p - radius).ceil().max(0.) as usize;
+                let last = ((p + radius).floor().max(0.) as usize).min(size - 1);
+                first..=last
+            });
+
+        for j in y_range {
+            for i in x_range.clone() {
+                let weight = filter.evaluate([position[0] - i as f32, position[1] - j as f32]);
+                if weight != 0. {
+                    let idx = self.index(i, j);
+                    self.sum[idx] += color * weight;
+                    self.weights[idx] += weight;
+                    for layer in self.layers.iter_mut().filter(|l| l.aov.is_lighting()) {
+                        layer.values[idx] += aovs[layer.aov] * weight;
+                    }
+                }
+            }
+        }
+
+        let idx = self.index(x, y);
+        for layer in self.layers.iter_mut() {
+            let value = aovs[layer.aov];
+            if layer.aov.is_id() {
+                if self.samples[idx] == 0 {
+                    layer.values[idx] = value;
+                }
+            } else if !layer.aov.is_lighting() {
+                layer.values[idx] += value;
+            }
+        }
+        self.count_sample(idx, color);
+    }
+
+    // the weighted mean of the samples around this pixel, or black if there are none
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let idx = self.index(x, y);
+        Self::resolve(self.sum[idx], self.weights[idx])
+    }
+
+    pub fn sample_count(&self, x: usize, y: usize) -> u32 {
+        self.samples[self.index(x, y)]
+    }
+
+    // the sample variance of the pixel's luminance, infinite until there are two samples
+    pub fn variance(&self, x: usize, y: usize) -> f32 {
+        let idx = self.index(x, y);
+        match self.samples[idx] {
+            0 | 1 => f32::INFINITY,
+            n => self.moments[idx].m2 / (n - 1) as f32,
+        }
+    }
+
+    // the estimated standard error of the pixel's mean luminance, relative to that mean
+    pub fn relative_error(&self, x: usize, y: usize) -> f32 {
+        let idx = self.index(x, y);
+        let standard_error = (self.variance(x, y) / self.samples[idx] as f32).sqrt();
+        standard_error / self.moments[idx].mean.max(ERROR_FLOOR)
+    }
+
+    // row-major sample counts, top row first
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.samples
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.samples.iter().map(|&n| n as u64).sum()
+    }
+
+    pub fn aovs(&self) -> Vec<Aov> {
+        self.layers.iter().map(|layer| layer.aov).collect()
+    }
+
+    // the row-major values of `aov`, if the film keeps it
+    pub fn aov_pixels(&self, aov: Aov) -> Option<Vec<Color>> {
+        let layer = self.layers.iter().find(|layer| layer.aov == aov)?;
+        let pixels = if aov.is_lighting() {
+            layer
+                .values
+                .iter()
+                .zip(&self.weights)
+                .map(|(&sum, &weight)| Self::resolve(sum, weight))
+                .collect()
+        } else if aov.is_id() {
+            layer.values.clone()
+        } else {
+            // unlike colors, AOVs such as normals can be negative
+            layer
+                .values
+                .iter()
+                .zip(&self.samples)
+                .map(|(&sum, &n)| if n == 0 { Color::ZERO } else { sum / n as f32 })
+                .collect()
+        };
+
+        Some(pixels)
+    }
+
+    // row-major pixel colors, top row first
+    pub fn pixels(&self) -> Vec<Color> {
+        self.sum
+            .iter()
+            .zip(&self.weights)
+            .map(|(&sum, &weight)| Self::resolve(sum, weight))
+            .collect()
+    }
+
+    // adds the samples of `other` to the pixels it covers, with its top left at (x, y); the parts
+    // outside of this film are left out
+    pub fn add_film(&mut self, x: isize, y: isize, other: &Film) {
+        assert_eq!(
+            self.aovs(),
+            other.aovs(),
+            "Films with different AOVs can't be combined!"
+        );
+        for row in 0..other.height {
+            for column in 0..other.width {
+                let (Ok(self_x), Ok(self_y)) = (
+                    usize::try_from(x + column as isize),
+                    usize::try_from(y + row as isize),
+                ) else {
+                    continue;
+                };
+                if self_x >= self.width || self_y >= self.height {
+                    continue;
+                }
+
+                let idx = self.index(self_x, self_y);
+                let other_idx = other.index(column, row);
+                let other_n = other.samples[other_idx];
+
+                self.sum[idx] += other.sum[other_idx];
+                self.weights[idx] += other.weights[other_idx];
+                for (layer, other_layer) in self.layers.iter_mut().zip(&other.layers) {
+                    let value = other_layer.values[other_idx];
+                    if !layer.aov.is_id() {
+                        layer.values[idx] += value;
+                    } else if self.samples[idx] == 0 && other_n > 0 {
+                        layer.values[idx] = value;
+                    }
+                }
+                self.moments[idx].merge(self.samples[idx], other.moments[other_idx], other_n);
+                self.samples[idx] += other_n;
+            }
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), OutputError> {
+        output::save(self, path)
+    }
+
+    pub fn save_with_format<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: OutputFormat,
+    ) -> Result<(), OutputError> {
+        output::save_with_format(self, path, format)
+    }
+
+    fn count_sample(&mut self, idx: usize, color: Color) {
+        self.samples[idx] += 1;
+        self.moments[idx].add(luminance(color), self.samples[idx]);
+    }
+
+    // filters with negative lobes can leave pixels with little or negative weight, or push them
+    // below black next to bright edges
+    fn resolve(sum: Color, weight: f32) -> Color {
+        if weight <= 0. {
+            Color::ZERO
+        } else {
+            (sum / weight).max(Color::ZERO)
+        }
+    }
+}
+
+impl Moments {
+    // `n` counts `x` already
+    fn add(&mut self, x: f32, n: u32) {
+        let delta = x - self.mean;
+        self.mean += delta / n as f32;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    // combines the moments of `n` and `other_n` samples (Chan et al.)
+    fn merge(&mut self, n: u32, other: Moments, other_n: u32) {
+        if other_n == 0 {
+            return;
+        }
+
+        let total = (n + other_n) as f32;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other_n as f32 / total;
+        self.m2 += other.m2 + delta * delta * n as f32 * other_n as f32 / total;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= EPSILON * a.abs().max(1.)
+    }
+
+    // luminances with a spread of magnitudes, so that rounding would show
+    fn luminances() -> Vec<f32> {
+        (0..37)
+            .map(|i| ((i * 7919) % 101) as f32 / 7. + 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn merged_moments_match_a_single_pass() {
+        let values = luminances();
+        let mut all = Moments::default();
+        for (i, &x) in values.iter().enumerate() {
+            all.add(x, i as u32 + 1);
+        }
+
+        for split in [1, 10, 18, 36] {
+            let (first, second) = values.split_at(split);
+            let mut a = Moments::default();
+            for (i, &x) in first.iter().enumerate() {
+                a.add(x, i as u32 + 1);
+            }
+            let mut b = Moments::default();
+            for (i, &x) in second.iter().enumerate() {
+                b.add(x, i as u32 + 1);
+            }
+
+            a.merge(first.len() as u32, b, second.len() as u32);
+            assert!(close(a.mean, all.mean), "{} != {}", a.mean, all.mean);
+            assert!(close(a.m2, all.m2), "{} != {}", a.m2, all.m2);
+        }
+    }
+
+    #[test]
+    fn merging_nothing_keeps_the_moments() {
+        let mut moments = Moments::default();
+        moments.add(2., 1);
+        moments.add(4., 2);
+        let before = (moments.mean, moments.m2);
+
+        moments.merge(2, Moments::default(), 0);
+        assert_eq!((moments.mean, moments.m2), before);
+    }
+
+    #[test]
+    fn relative_error_of_known_samples() {
+        let mut film = Film::new(2, 1);
+        assert_eq!(film.relative_error(0, 0), f32::INFINITY);
+        film.add_sample(0, 0, Color::splat(1.));
+        assert_eq!(film.relative_error(0, 0), f32::INFINITY);
+
+        // a mean of 2 and a sample variance of 2, so the standard error is 1
+        film.add_sample(0, 0, Color::splat(3.));
+        assert!(close(film.variance(0, 0), 2.));
+        assert!(close(film.relative_error(0, 0), 0.5));
+
+        // identical samples have no error at all
+        for _ in 0..4 {
+            film.add_sample(1, 0, Color::splat(0.7));
+        }
+        assert!(film.relative_error(1, 0) < EPSILON);
+    }
+
+    #[test]
+    fn dark_pixels_are_judged_against_the_floor() {
+        let mut film = Film::new(1, 1);
+        film.add_sample(0, 0, Color::ZERO);
+        film.add_sample(0, 0, Color::splat(0.002));
+
+        // a standard error of 0.001, which is a tenth of the floor
+        assert!(close(film.relative_error(0, 0), 0.1));
+    }
+
+    #[test]
+    fn added_films_keep_the_error_estimate() {
+        let values = luminances();
+        let mut whole = Film::new(1, 1);
+        let (mut first, mut second) = (Film::new(1, 1), Film::new(1, 1));
+        for (i, &x) in values.iter().enumerate() {
+            whole.add_sample(0, 0, Color::splat(x));
+            let half = if i < 15 { &mut first } else { &mut second };
+            half.add_sample(0, 0, Color::splat(x));
+        }
+
+        first.add_film(0, 0, &second);
+        assert_eq!(first.sample_count(0, 0), whole.sample_count(0, 0));
+        assert!(close(
+            first.relative_error(0, 0),
+            whole.relative_error(0, 0)
+        ));
+    }
+
+    // the pixels a sample at `offset` from the center of (x, y) lands in, by their weights
+    fn splat(film_size: [usize; 2], pixel: [usize; 2], offset: [f32; 2], filter: Filter) -> Film {
+        let mut film = Film::new(film_size[0], film_size[1]);
+        let aovs = AovSample::default();
+        film.add_filtered_sample(pixel[0], pixel[1], offset, Color::ONE, &aovs, filter);
+        film
+    }
+
+    fn reached(film: &Film) -> Vec<[usize; 2]> {
+        let mut pixels = Vec::new();
+        for y in 0..film.height() {
+            for x in 0..film.width() {
+                if film.weights[film.index(x, y)] != 0. {
+                    pixels.push([x, y]);
+                }
+            }
+        }
+
+        pixels
+    }
+
+    #[test]
+    fn box_filter_keeps_samples_in_their_pixel() {
+        let offsets = [
+            [0., 0.],
+            [-0.5, -0.5],
+            [-0.5, 0.4999],
+            [0.4999, -0.5],
+            [0.4999, 0.4999],
+        ];
+        for offset in offsets {
+            let film = splat([3, 3], [1, 1], offset, Filter::default());
+            assert_eq!(reached(&film), [[1, 1]], "{offset:?}");
+            assert_eq!(film.pixel(1, 1), Color::ONE);
+        }
+
+        // the same as adding the samples without a filter
+        let mut filtered = Film::new(2, 2);
+        let mut unfiltered = Film::new(2, 2);
+        for (i, offset) in offsets.into_iter().enumerate() {
+            let color = Color::splat(i as f32);
+            let aovs = AovSample::default();
+            filtered.add_filtered_sample(1, 0, offset, color, &aovs, Filter::default());
+            unfiltered.add_sample(1, 0, color);
+        }
+        assert_eq!(filtered.pixels(), unfiltered.pixels());
+    }
+
+    #[test]
+    fn wide_filters_reach_the_pixels_within_their_radius() {
+        let tent = Filter::from_name("tent").unwrap();
+        assert_eq!(
+            reached(&splat([4, 3], [1, 1], [0.25, 0.], tent)),
+            [[1, 1], [2, 1]]
+        );
+        assert_eq!(
+            reached(&splat([4, 3], [1, 1], [-0.25, 0.25], tent)),
+            [[0, 1], [1, 1], [0, 2], [1, 2]]
+        );
+
+        // the sample is counted once, in its own pixel
+        let film = splat([4, 3], [1, 1], [-0.25, 0.25], tent);
+        assert_eq!(film.total_samples(), 1);
+        assert_eq!(film.sample_count(1, 1), 1);
+    }
+
+    #[test]
+    fn splats_stop_at_the_border() {
+        let lanczos = Filter::from_name("lanczos").unwrap();
+
+        // the corners, with the sample as far out as it goes
+        let film = splat([5, 4], [0, 0], [-0.5, -0.5], lanczos);
+        let pixels = reached(&film);
+        assert!(pixels.contains(&[0, 0]) && pixels.contains(&[2, 2]));
+        assert!(pixels.iter().all(|&[x, y]| x <= 2 && y <= 2), "{pixels:?}");
+
+        let film = splat([5, 4], [4, 3], [0.4999, 0.4999], lanczos);
+        let pixels = reached(&film);
+        assert!(pixels.contains(&[4, 3]) && pixels.contains(&[2, 1]));
+        assert!(pixels.iter().all(|&[x, y]| x >= 2 && y >= 1), "{pixels:?}");
+
+        // a filter wider than the whole film
+        let gaussian = Filter::from_name("gaussian").unwrap().with_radius(10.);
+        let film = splat([2, 2], [1, 0], [0.3, -0.2], gaussian);
+        assert_eq!(reached(&film), [[0, 0], [1, 0], [0, 1], [1, 1]]);
+
+        // and single pixel films
+        for filter in [Filter::default(), lanczos, gaussian] {
+            assert_eq!(
+                reached(&splat([1, 1], [0, 0], [-0.5, 0.4999], filter)),
+                [[0, 0]]
+            );
+        }
+    }
+
+    fn aov_sample(values: &[(Aov, Color)]) -> AovSample {
+        let mut sample = AovSample::default();
+        for &(aov, value) in values {
+            sample[aov] = value;
+        }
+        sample
+    }
+
+    fn close_colors(a: Color, b: Color) -> bool {
+        a.as_array()
+            .iter()
+            .zip(b.as_array())
+            .all(|(&a, &b)| close(a, b))
+    }
+
+    #[test]
+    fn lighting_aovs_add_up_to_the_image() {
+        let lighting = [Aov::Direct, Aov::Indirect, Aov::Emission];
+        let mut film = Film::with_aovs(4, 3, &lighting);
+        let mut tile = Film::with_aovs(2, 2, &lighting);
+        let tent = Filter::from_name("tent").unwrap();
+
+        for i in 0..40 {
+            let (x, y) = (i % 4, i / 4 % 3);
+            let offset = [(i % 7) as f32 / 7. - 0.5, (i % 5) as f32 / 5. - 0.5];
+            let [direct, indirect, emission] =
+                [0.3, 1.7, 0.1].map(|scale| Color::new(0.2, 0.5, 0.9) * scale * (i % 3) as f32);
+            let aovs = aov_sample(&[
+                (Aov::Direct, direct),
+                (Aov::Indirect, indirect),
+                (Aov::Emission, emission),
+            ]);
+            let color = direct + indirect + emission;
+            film.add_filtered_sample(x, y, offset, color, &aovs, tent);
+            if x < 2 && y < 2 {
+                tile.add_filtered_sample(x, y, offset, color, &aovs, tent);
+            }
+        }
+        film.add_film(2, 1, &tile);
+
+        let layers = lighting.map(|aov| film.aov_pixels(aov).unwrap());
+        for (i, pixel) in film.pixels().into_iter().enumerate() {
+            let sum = layers[0][i] + layers[1][i] + layers[2][i];
+            assert!(close_colors(sum, pixel), "{sum:?} != {pixel:?}");
+        }
+    }
+
+    #[test]
+    fn id_aovs_keep_the_first_sample() {
+        let ids = [Aov::ObjectId, Aov::MaterialId];
+        let sample = |id: f32| {
+            aov_sample(&[
+                (Aov::ObjectId, Color::splat(id)),
+                (Aov::MaterialId, Color::splat(id + 10.)),
+            ])
+        };
+        let mitchell = Filter::from_name("mitchell").unwrap();
+
+        let mut film = Film::with_aovs(3, 1, &ids);
+        film.add_filtered_sample(1, 0, [0.2, 0.], Color::ONE, &sample(3.), mitchell);
+        film.add_filtered_sample(1, 0, [-0.2, 0.], Color::ONE, &sample(4.), mitchell);
+
+        // the other film's samples come after this one's where both have some
+        let mut other = Film::with_aovs(3, 1, &ids);
+        other.add_filtered_sample(1, 0, [0., 0.], Color::ONE, &sample(5.), mitchell);
+        other.add_filtered_sample(2, 0, [0., 0.], Color::ONE, &sample(6.), mitchell);
+        other.add_filtered_sample(2, 0, [0., 0.], Color::ONE, &sample(7.), mitchell);
+        film.add_film(0, 0, &other);
+
+        let object_ids = film.aov_pixels(Aov::ObjectId).unwrap();
+        assert_eq!(object_ids, [0., 3., 6.].map(Color::splat));
+        let material_ids = film.aov_pixels(Aov::MaterialId).unwrap();
+        assert_eq!(material_ids, [0., 13., 16.].map(Color::splat));
+
+        // neither the filter nor merging blends them
+        let mut empty = Film::with_aovs(3, 1, &ids);
+        empty.add_film(0, 0, &other);
+        assert_eq!(
+            empty.aov_pixels(Aov::ObjectId).unwrap(),
+            [0., 5., 6.].map(Color::splat)
+        );
+    }
+
+    #[test]
+    fn other_aovs_average_their_own_samples() {
+        let aovs = [Aov::Depth, Aov::Normal];
+        let sample = |depth: f32, normal: Color| {
+            aov_sample(&[(Aov::Depth, Color::splat(depth)), (Aov::Normal, normal)])
+        };
+        let gaussian = Filter::from_name("gaussian").unwrap();
+
+        let mut film = Film::with_aovs(3, 1, &aovs);
+        let up = Color::new(0., 1., 0.);
+        let down = Color::new(0., -1., 0.);
+        film.add_filtered_sample(1, 0, [0.4, 0.], Color::ONE, &sample(2., up), gaussian);
+        film.add_filtered_sample(1, 0, [-0.4, 0.], Color::ONE, &sample(4., up), gaussian);
+
+        let mut other = Film::with_aovs(3, 1, &aovs);
+        other.add_filtered_sample(1, 0, [0., 0.], Color::ONE, &sample(9., down), gaussian);
+        film.add_film(0, 0, &other);
+
+        // the filter spreads the image into the neighbours, but not the AOVs
+        assert!(film.pixel(0, 0) != Color::ZERO);
+        let depth = film.aov_pixels(Aov::Depth).unwrap();
+        assert_eq!(depth, [0., 5., 0.].map(Color::splat));
+        let normal = film.aov_pixels(Aov::Normal).unwrap();
+        assert!(close_colors(normal[1], Color::new(0., 1. / 3., 0.)));
+        assert_eq!((normal[0], normal[2]), (Color::ZERO, Color::ZERO));
+    }
+}
@@ -0,0 +1,226 @@
+use std::f32::consts::PI;
+
+// How a sample is shared between the pixels around it: each pixel whose center is within `radius`
+// of the sample (along both axes) gets it with the filter's weight at that offset. A box of
+// radius 0.5 keeps every sample in its own pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box { radius: f32 },
+    // falls off linearly to the radius
+    Tent { radius: f32 },
+    // shifted down to reach zero at the radius
+    Gaussian { radius: f32, sigma: f32 },
+    // the cubic of Mitchell and Netravali, which sharpens a little with its negative lobes; the
+    // recommended `b` and `c` are both 1/3
+    Mitchell { radius: f32, b: f32, c: f32 },
+    // a sinc windowed by a wider one, with as many lobes as the radius
+    Lanczos { radius: f32 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    // the filter with its usual radius and parameters
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Self::Box { radius: 0.5 }),
+            "tent" => Some(Self::Tent { radius: 1. }),
+            "gaussian" => Some(Self::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            }),
+            "mitchell" => Some(Self::Mitchell {
+                radius: 2.,
+                b: 1. / 3.,
+                c: 1. / 3.,
+            }),
+            "lanczos" => Some(Self::Lanczos { radius: 3. }),
+            _ => None,
+        }
+    }
+
+    pub fn radius(self) -> f32 {
+        match self {
+            Self::Box { radius }
+            | Self::Tent { radius }
+            | Self::Gaussian { radius, .. }
+            | Self::Mitchell { radius, .. }
+            | Self::Lanczos { radius } => radius,
+        }
+    }
+
+    pub fn with_radius(mut self, new_radius: f32) -> Self {
+        match &mut self {
+            Self::Box { radius }
+            | Self::Tent { radius }
+            | Self::Gaussian { radius, .. }
+            | Self::Mitchell { radius, .. }
+            | Self::Lanczos { radius } => *radius = new_radius,
+        }
+        self
+    }
+
+    // the weight of a sample `offset` away from a pixel's center, which can be negative
+    pub fn evaluate(self, offset: [f32; 2]) -> f32 {
+        let [x, y] = offset.map(|d| self.evaluate_1d(d));
+        x * y
+    }
+
+    fn evaluate_1d(self, d: f32) -> f32 {
+        match self {
+            // half open, so that a sample on the border between two pixels only counts for one
+            Self::Box { radius } => {
+                if (-radius..radius).contains(&d) {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Self::Tent { radius } => (1. - d.abs() / radius).max(0.),
+            Self::Gaussian { radius, sigma } => {
+                let gaussian = |d: f32| (-d * d / (2. * sigma * sigma)).exp();
+                (gaussian(d) - gaussian(radius)).max(0.)
+            }
+            Self::Mitchell { radius, b, c } => {
+                let x = (2. * d / radius).abs();
+                if x >= 2. {
+                    0.
+                } else if x >= 1. {
+                    ((-b - 6. * c) * x * x * x
+                        + (6. * b + 30. * c) * x * x
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                } else {
+                    ((12. - 9. * b - 6. * c) * x * x * x
+                        + (-18. + 12. * b + 6. * c) * x * x
+                        + (6. - 2. * b))
+                        / 6.
+                }
+            }
+            Self::Lanczos { radius } => {
+                if d.abs() >= radius {
+                    0.
+                } else {
+                    sinc(d) * sinc(d / radius)
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 5] = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+    fn filters() -> impl Iterator<Item = Filter> {
+        NAMES
+            .into_iter()
+            .map(|name| Filter::from_name(name).unwrap())
+    }
+
+    #[test]
+    fn weights_peak_at_the_center() {
+        for filter in filters() {
+            let center = filter.evaluate([0., 0.]);
+            assert!(center > 0., "{filter:?}");
+            for d in [0.1, 0.3, 0.45] {
+                for offset in [[d, 0.], [0., -d], [d, d]] {
+                    assert!(
+                        filter.evaluate(offset) <= center,
+                        "{filter:?} at {offset:?}"
+                    );
+                }
+            }
+        }
+
+        assert_eq!(Filter::from_name("box").unwrap().evaluate([0., 0.]), 1.);
+        assert_eq!(Filter::from_name("tent").unwrap().evaluate([0., 0.]), 1.);
+        assert_eq!(Filter::from_name("lanczos").unwrap().evaluate([0., 0.]), 1.);
+        // (6 - 2b) / 6 along each axis
+        let mitchell = Filter::from_name("mitchell").unwrap().evaluate([0., 0.]);
+        assert!((mitchell - (8. / 9.) * (8. / 9.)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn weights_vanish_from_the_radius_on() {
+        for filter in filters().chain(filters().map(|f| f.with_radius(2.5))) {
+            let radius = filter.radius();
+            for d in [radius, radius + 1e-3, radius + 0.5, 2. * radius, 100.] {
+                for offset in [[d, 0.], [0., d], [d, d]] {
+                    assert_eq!(filter.evaluate(offset), 0., "{filter:?} at {offset:?}");
+                }
+                // the box keeps its lower edge
+                if d > radius {
+                    for offset in [[-d, 0.], [0., -d]] {
+                        assert_eq!(filter.evaluate(offset), 0., "{filter:?} at {offset:?}");
+                    }
+                }
+            }
+
+            // but not just inside it, except where the Lanczos window has a zero
+            if !matches!(filter, Filter::Lanczos { .. }) {
+                let inside = radius * 0.99;
+                assert!(filter.evaluate([inside, 0.]) != 0., "{filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn box_is_half_open() {
+        let filter = Filter::default();
+        assert_eq!(filter.evaluate([-0.5, -0.5]), 1.);
+        assert_eq!(filter.evaluate([0.4999, 0.]), 1.);
+        assert_eq!(filter.evaluate([0.5, 0.]), 0.);
+        assert_eq!(filter.evaluate([0., 0.5]), 0.);
+    }
+
+    #[test]
+    fn filters_are_symmetric() {
+        for filter in filters() {
+            for d in [0.2, 0.7, 1.3] {
+                let weight = filter.evaluate([d, 0.3]);
+                assert_eq!(filter.evaluate([-d, 0.3]), weight, "{filter:?}");
+                assert_eq!(filter.evaluate([0.3, d]), weight, "{filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn mitchell_and_lanczos_have_negative_lobes() {
+        let mitchell = Filter::from_name("mitchell").unwrap();
+        // between 1 and 2 in the cubic's own units, so between half the radius and the radius
+        assert!(mitchell.evaluate([1.5, 0.]) < 0.);
+        assert!(mitchell.evaluate([0.5, 0.]) > 0.);
+
+        let lanczos = Filter::from_name("lanczos").unwrap();
+        assert!(lanczos.evaluate([1.5, 0.]) < 0.);
+        assert!(lanczos.evaluate([2.5, 0.]) > 0.);
+        // the sinc's zeros stay where they are
+        assert!(lanczos.evaluate([1., 0.]).abs() < 1e-6);
+        assert!(lanczos.evaluate([2., 0.]).abs() < 1e-6);
+
+        // the other filters are never negative
+        for name in ["box", "tent", "gaussian"] {
+            let filter = Filter::from_name(name).unwrap();
+            for i in 0..40 {
+                let d = i as f32 * 0.05;
+                assert!(filter.evaluate([d, 0.3]) >= 0., "{name} at {d}");
+            }
+        }
+    }
+}
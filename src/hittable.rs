@@ -2,12 +2,17 @@ pub mod aabb;
 pub mod bvh;
 pub mod instances;
 pub mod interval;
+pub mod mesh;
 pub mod quad;
 pub mod sphere;
+pub mod triangle;
+
+use rand::Rng;
 
 use crate::{
     material::Material,
     ray::Ray,
+    sampler::SampleRng,
     units::{Point, TexCoord, Vector},
 };
 
@@ -22,11 +27,24 @@ pub struct HitRecord<'a> {
     pub t: f32,
     pub uv: TexCoord,
     pub front_face: bool,
+    // set by `Identified`, 0 otherwise
+    pub object_id: u32,
 }
 
 pub trait Hittable: Sync {
     fn hit(&self, ray: &Ray, ray_t: Interval<f32>) -> Option<HitRecord>;
     fn bounding_box(&self) -> AABB<f32>;
+
+    // Light sampling: the solid angle density with which `random` picks `direction` from
+    // `origin`. Objects that can't be sampled leave both methods alone and are never picked.
+    fn pdf_value(&self, _origin: Point, _direction: Vector, _time: f32) -> f32 {
+        0.0
+    }
+
+    // a (not necessarily normalized) direction from `origin` towards a point on this object
+    fn random(&self, _origin: Point, _time: f32, _rng: &mut SampleRng) -> Vector {
+        Vector::new(1.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Default)]
@@ -35,6 +53,13 @@ pub struct HittableList<'a> {
     aabb: AABB<f32>,
 }
 
+// An object that tells its hits apart from other objects' in the object ID AOV. Wrapping a group
+// gives all of it the same ID.
+pub struct Identified<'a> {
+    pub object: &'a dyn Hittable,
+    pub id: u32,
+}
+
 impl<'a> HitRecord<'a> {
     pub fn new(
         ray: &Ray,
@@ -60,8 +85,21 @@ impl<'a> HitRecord<'a> {
             front_face,
             uv,
             mat,
+            object_id: 0,
         }
     }
+
+    // replaces the normal used for shading (e.g. an interpolated vertex normal), keeping it on
+    // the same side of the surface as the geometric normal; vertex normals don't always agree
+    // with the winding of their faces, so the side is taken from `self.normal` alone
+    pub fn with_shading_normal(mut self, shading_normal: Vector) -> Self {
+        self.normal = if shading_normal.dot(self.normal) < 0. {
+            -shading_normal
+        } else {
+            shading_normal
+        };
+        self
+    }
 }
 
 impl<'a> HittableList<'a> {
@@ -89,6 +127,14 @@ impl<'a> HittableList<'a> {
         self.aabb = self.aabb.combine(object.bounding_box());
     }
 
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
     pub fn from_vec(objects: Vec<&'a dyn Hittable>) -> Self {
         let mut aabb = AABB::EMPTY;
         for hittable in &objects {
@@ -121,4 +167,47 @@ impl Hittable for HittableList<'_> {
     fn bounding_box(&self) -> AABB<f32> {
         self.aabb
     }
+
+    // picks each object with equal probability
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        let sum: f32 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction, time))
+            .sum();
+        sum / self.objects.len() as f32
+    }
+
+    fn random(&self, origin: Point, time: f32, rng: &mut SampleRng) -> Vector {
+        if self.objects.is_empty() {
+            return Vector::new(1.0, 0.0, 0.0);
+        }
+
+        let idx = rng.gen_range(0..self.objects.len());
+        self.objects[idx].random(origin, time, rng)
+    }
+}
+
+impl Hittable for Identified<'_> {
+    fn hit(&self, ray: &Ray, ray_t: Interval<f32>) -> Option<HitRecord> {
+        let mut hit = self.object.hit(ray, ray_t)?;
+        hit.object_id = self.id;
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> AABB<f32> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        self.object.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: Point, time: f32, rng: &mut SampleRng) -> Vector {
+        self.object.random(origin, time, rng)
+    }
 }
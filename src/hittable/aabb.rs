@@ -140,6 +140,15 @@ impl AABB<f32> {
         self
     }
 
+    pub fn surface_area(self) -> f32 {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2. * (x * y + y * z + z * x)
+    }
+
+    pub fn centroid(self) -> [f32; 3] {
+        [0, 1, 2].map(|axis| (self[axis].min + self[axis].max) / 2.)
+    }
+
     pub fn offset(mut self, offset: Vector) -> AABB<f32> {
         for axis in 0..3 {
             self[axis] = self[axis].offset(offset[axis]);
@@ -1,44 +1,218 @@
-use core::alloc::Allocator;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
 
 use crate::ray::Ray;
 
 use super::{HitRecord, Hittable, Interval, AABB};
 
-pub struct BvhNode<'a> {
-    l: &'a dyn Hittable,
-    r: &'a dyn Hittable,
+// A BVH laid out flat: the nodes live in one `Vec` in depth-first order, so the first child of an
+// interior node directly follows it, and every leaf refers to a range of `objects`.
+pub struct Bvh<'a> {
+    nodes: Vec<BvhNode>,
+    objects: Vec<&'a dyn Hittable>,
+}
+
+#[derive(Clone, Copy)]
+struct BvhNode {
     aabb: AABB<f32>,
+    // the first object of a leaf, or the second child of an interior node
+    offset: u32,
+    // the number of objects in a leaf, zero for interior nodes
+    count: u32,
+    // the axis an interior node was split along, to visit the nearer child first
+    axis: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvhStrategy {
+    // split at the median object along a round-robin axis, down to single objects
+    Median,
+    // binned surface area heuristic, keeping up to `max_leaf_size` objects in a leaf when testing
+    // them all is expected to be cheaper than splitting further
+    Sah { max_leaf_size: usize },
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BvhStats {
+    pub build_time: Duration,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_leaf_size: usize,
+    pub max_depth: usize,
+    // expected cost of a ray entering the root, in units of object intersection tests
+    pub sah_cost: f32,
 }
 
-impl<'a> BvhNode<'a> {
-    pub fn new<'b: 'a, A: Allocator + Copy + 'a>(
-        mut objects: Vec<&'a dyn Hittable>,
-        alloc: A,
-    ) -> Self {
-        if objects.len() < 2 {
-            panic!("Cannot construct a BvhNode with < 2 elements!")
+// relative to the cost of intersecting an object
+const TRAVERSAL_COST: f32 = 0.125;
+const SAH_BINS: usize = 16;
+// bounds the traversal stack; anything deeper is put in a leaf
+const MAX_DEPTH: usize = 64;
+
+impl BvhStrategy {
+    pub const SAH: Self = Self::Sah { max_leaf_size: 4 };
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "median" => Some(Self::Median),
+            "sah" => Some(Self::SAH),
+            _ => None,
         }
+    }
+}
 
-        Self::construct(alloc, &mut objects, 0)
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves of up to {} objects, depth {}, SAH cost {:.2}, built in {:.2?}",
+            self.nodes,
+            self.leaves,
+            self.max_leaf_size,
+            self.max_depth,
+            self.sah_cost,
+            self.build_time
+        )
     }
+}
 
-    fn construct<'b: 'a, A: Allocator + Copy + 'a>(
-        alloc: A,
-        objects: &mut [&'a dyn Hittable],
+impl<'a> Bvh<'a> {
+    pub fn new(objects: Vec<&'a dyn Hittable>) -> Self {
+        Self::new_with_strategy(objects, BvhStrategy::Median)
+    }
+
+    pub fn new_with_strategy(objects: Vec<&'a dyn Hittable>, strategy: BvhStrategy) -> Self {
+        Self::build(objects, strategy).0
+    }
+
+    // like `new_with_strategy`, but also reports on the shape of the tree
+    pub fn build(mut objects: Vec<&'a dyn Hittable>, strategy: BvhStrategy) -> (Self, BvhStats) {
+        let start = Instant::now();
+        let mut builder = Builder {
+            strategy,
+            nodes: Vec::with_capacity((2 * objects.len()).saturating_sub(1)),
+            stats: BvhStats::default(),
+        };
+
+        // an empty BVH has no nodes at all, and misses every ray
+        if objects.is_empty() {
+            let bvh = Self {
+                nodes: builder.nodes,
+                objects,
+            };
+            return (bvh, builder.stats);
+        }
+
+        let cost = builder.node(&mut objects, 0, 0, 0);
+
+        let mut stats = builder.stats;
+        stats.sah_cost = cost;
+        stats.build_time = start.elapsed();
+
+        let bvh = Self {
+            nodes: builder.nodes,
+            objects,
+        };
+
+        (bvh, stats)
+    }
+}
+
+struct Builder {
+    strategy: BvhStrategy,
+    nodes: Vec<BvhNode>,
+    stats: BvhStats,
+}
+
+impl Builder {
+    // appends the subtree for `objects`, which start at `first` in the final object array, and
+    // returns its cost
+    fn node(
+        &mut self,
+        objects: &mut [&dyn Hittable],
+        first: usize,
+        axis: usize,
+        depth: usize,
+    ) -> f32 {
+        let aabb = objects.iter().fold(AABB::EMPTY, |aabb, object| {
+            aabb.combine(object.bounding_box())
+        });
+
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb,
+            offset: first as u32,
+            count: objects.len() as u32,
+            axis: 0,
+        });
+
+        let Some((split, axis)) = self.split(objects, axis, depth) else {
+            self.stats.leaves += 1;
+            self.stats.max_leaf_size = self.stats.max_leaf_size.max(objects.len());
+            self.stats.max_depth = self.stats.max_depth.max(depth);
+            return objects.len() as f32;
+        };
+        self.stats.nodes += 1;
+
+        let (l, r) = objects.split_at_mut(split);
+        let next_axis = (axis + 1) % 3;
+        let l_cost = self.node(l, first, next_axis, depth + 1);
+        let second = self.nodes.len();
+        let r_cost = self.node(r, first + split, next_axis, depth + 1);
+
+        self.nodes[index] = BvhNode {
+            aabb,
+            offset: second as u32,
+            count: 0,
+            axis: axis as u8,
+        };
+
+        let area = aabb.surface_area();
+        if area > 0. {
+            let l_area = self.nodes[index + 1].aabb.surface_area();
+            let r_area = self.nodes[second].aabb.surface_area();
+            TRAVERSAL_COST + (l_area * l_cost + r_area * r_cost) / area
+        } else {
+            TRAVERSAL_COST + l_cost + r_cost
+        }
+    }
+
+    // partitions `objects` and returns where and along which axis to split them, or `None` to
+    // make them a leaf
+    fn split(
+        &self,
+        objects: &mut [&dyn Hittable],
         axis: usize,
-    ) -> Self {
-        let in_alloc = |node| Box::leak(Box::new_in(node, alloc));
+        depth: usize,
+    ) -> Option<(usize, usize)> {
+        if objects.len() == 1 || depth + 1 >= MAX_DEPTH {
+            return None;
+        }
 
-        // base cases
-        if objects.len() == 2 {
-            return Self::from_two(objects[0], objects[1]);
+        match self.strategy {
+            BvhStrategy::Median => Some((Self::median_split(objects, axis), axis)),
+            BvhStrategy::Sah { max_leaf_size } => match Self::sah_split(objects) {
+                Some((split, axis, cost))
+                    if objects.len() > max_leaf_size || cost < objects.len() as f32 =>
+                {
+                    Some((split, axis))
+                }
+                // every object has the same centroid, so there's no good way to divide them
+                None if objects.len() > max_leaf_size => Some((objects.len() / 2, axis)),
+                _ => None,
+            },
         }
-        if objects.len() == 3 {
-            let node_a = in_alloc(Self::from_two(objects[0], objects[1]));
-            return Self::from_two(node_a, objects[2]);
+    }
+
+    fn median_split(objects: &mut [&dyn Hittable], axis: usize) -> usize {
+        // pairs and triples are split in order
+        if objects.len() <= 3 {
+            return 2.min(objects.len() - 1);
         }
 
-        // recursive case - split into two a la k-d trees, and then recurse
+        // split into two a la k-d trees
         let split_idx = objects.len() / 2;
         objects.select_nth_unstable_by(split_idx, |l, r| {
             let l = l.bounding_box()[axis].min;
@@ -46,39 +220,295 @@ impl<'a> BvhNode<'a> {
             l.partial_cmp(&r).unwrap()
         });
 
-        let (l, r) = objects.split_at_mut(split_idx);
-        let axis = (axis + 1) % 3;
-        let l = in_alloc(Self::construct(alloc, l, axis));
-        let r = in_alloc(Self::construct(alloc, r, axis));
-
-        Self::from_two(l, r)
+        split_idx
     }
 
-    fn from_two(l: &'a dyn Hittable, r: &'a dyn Hittable) -> Self {
-        BvhNode {
-            l,
-            r,
-            aabb: l.bounding_box().combine(r.bounding_box()),
+    // buckets the objects by centroid along each axis and picks the bucket boundary minimising
+    // the surface area heuristic; partitions `objects` there and returns the split index and axis
+    // with the expected cost of splitting
+    fn sah_split(objects: &mut [&dyn Hittable]) -> Option<(usize, usize, f32)> {
+        let mut bounds = AABB::EMPTY;
+        let mut centroid_bounds = AABB::EMPTY;
+        for object in objects.iter() {
+            let aabb = object.bounding_box();
+            bounds = bounds.combine(aabb);
+            for (axis, c) in aabb.centroid().into_iter().enumerate() {
+                centroid_bounds[axis] = centroid_bounds[axis].insert(c);
+            }
         }
+
+        let area = bounds.surface_area();
+        let bin_of = |object: &&dyn Hittable, axis: usize| {
+            let extent = centroid_bounds[axis];
+            let c = object.bounding_box().centroid()[axis];
+            let bin = ((c - extent.min) / extent.size() * SAH_BINS as f32) as usize;
+            bin.min(SAH_BINS - 1)
+        };
+
+        // (cost, axis, last bin on the left)
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if centroid_bounds[axis].size() <= 0. {
+                continue;
+            }
+
+            let mut bins = [(AABB::EMPTY, 0); SAH_BINS];
+            for object in objects.iter() {
+                let (aabb, count) = &mut bins[bin_of(object, axis)];
+                *aabb = aabb.combine(object.bounding_box());
+                *count += 1;
+            }
+
+            // sweep from the right to get the area and count of every suffix of bins
+            let mut right = [(0., 0); SAH_BINS];
+            let (mut aabb, mut count) = (AABB::EMPTY, 0);
+            for bin in (1..SAH_BINS).rev() {
+                aabb = aabb.combine(bins[bin].0);
+                count += bins[bin].1;
+                right[bin] = (aabb.surface_area(), count);
+            }
+
+            let (mut aabb, mut count) = (AABB::EMPTY, 0);
+            for bin in 0..SAH_BINS - 1 {
+                aabb = aabb.combine(bins[bin].0);
+                count += bins[bin].1;
+                let (right_area, right_count) = right[bin + 1];
+                if count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let weighted = aabb.surface_area() * count as f32 + right_area * right_count as f32;
+                let cost = if area > 0. {
+                    TRAVERSAL_COST + weighted / area
+                } else {
+                    TRAVERSAL_COST + objects.len() as f32
+                };
+                if !best.is_some_and(|(best_cost, _, _)| best_cost <= cost) {
+                    best = Some((cost, axis, bin));
+                }
+            }
+        }
+
+        let (cost, axis, last_bin) = best?;
+
+        let mut split = 0;
+        for i in 0..objects.len() {
+            if bin_of(&objects[i], axis) <= last_bin {
+                objects.swap(i, split);
+                split += 1;
+            }
+        }
+
+        Some((split, axis, cost))
     }
 }
 
-impl Hittable for BvhNode<'_> {
+impl Hittable for Bvh<'_> {
     fn hit(&self, ray: &Ray, mut ray_t: Interval<f32>) -> Option<HitRecord> {
-        if !self.aabb.hit(ray, ray_t) {
+        if self.nodes.is_empty() {
             return None;
         }
 
-        let hit_l = self.l.hit(ray, ray_t);
-        if let Some(HitRecord { t, .. }) = hit_l {
-            ray_t.max = t;
+        let mut hit_record = None;
+
+        let mut stack = [0; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            if node.aabb.hit(ray, ray_t) {
+                if node.count > 0 {
+                    let objects = node.offset as usize..(node.offset + node.count) as usize;
+                    for object in &self.objects[objects] {
+                        let hit = object.hit(ray, ray_t);
+                        if let Some(ref rec) = hit {
+                            ray_t.max = rec.t;
+                            hit_record = hit;
+                        }
+                    }
+                } else {
+                    // descend into the nearer child, and come back for the other one later
+                    let (near, far) = if ray.direct[node.axis as usize] < 0. {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    index = near;
+                    continue;
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len];
         }
-        let hit_r = self.r.hit(ray, ray_t);
 
-        hit_r.or(hit_l)
+        hit_record
     }
 
     fn bounding_box(&self) -> AABB<f32> {
-        self.aabb
+        self.nodes.first().map_or(AABB::EMPTY, |root| root.aabb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        hittable::{quad::Quad, HittableList, Sphere},
+        material::Material,
+        time_utils::Unchanging,
+        units::{Point, RenderRng, Vector},
+    };
+
+    use super::*;
+
+    struct Blank;
+
+    impl Material for Blank {}
+
+    fn leak<T>(x: T) -> &'static T {
+        Box::leak(Box::new(x))
+    }
+
+    fn random_point(rng: &mut RenderRng, extent: f32) -> Point {
+        Point::new(
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+        )
+    }
+
+    // clusters of small spheres and quads, some of them spheres of about the same size packed so
+    // tightly that the SAH keeps several in a leaf
+    fn random_scene(rng: &mut RenderRng) -> Vec<&'static dyn Hittable> {
+        let mut objects: Vec<&dyn Hittable> = Vec::new();
+        for _ in 0..12 {
+            let center = random_point(rng, 10.);
+            if rng.gen_bool(0.3) {
+                for _ in 0..rng.gen_range(2..5) {
+                    let center = center + random_point(rng, 0.01).to_vector();
+                    let radius = rng.gen_range(0.49..0.5);
+                    objects.push(leak(Sphere::<Unchanging>::new(center, radius, &Blank)));
+                }
+                continue;
+            }
+
+            for _ in 0..rng.gen_range(1..20) {
+                let corner = center + random_point(rng, 1.5).to_vector();
+                if rng.gen_bool(0.7) {
+                    let radius = rng.gen_range(0.05..0.6);
+                    objects.push(leak(Sphere::<Unchanging>::new(corner, radius, &Blank)));
+                } else {
+                    let [u, v] = [0; 2].map(|_| random_point(rng, 1.).to_vector());
+                    objects.push(leak(Quad::new(corner, u, v, &Blank)));
+                }
+            }
+        }
+
+        objects
+    }
+
+    // from anywhere around the scene, towards somewhere near one of `objects`
+    fn random_ray(rng: &mut RenderRng, objects: &[&dyn Hittable]) -> Ray {
+        let origin = random_point(rng, 14.);
+        let [x, y, z] = objects[rng.gen_range(0..objects.len())]
+            .bounding_box()
+            .centroid();
+        let target = Point::new(x, y, z) + random_point(rng, 0.5).to_vector();
+        Ray {
+            origin,
+            direct: target - origin,
+            time: 0.,
+        }
+    }
+
+    fn closest(hit: Option<HitRecord>) -> Option<(f32, Point, Vector)> {
+        hit.map(|hit| (hit.t, hit.p, hit.normal))
+    }
+
+    #[test]
+    fn bvhs_find_the_same_hits_as_a_list() {
+        let mut rng = RenderRng::seed_from_u64(1);
+        for _ in 0..4 {
+            let objects = random_scene(&mut rng);
+            let list = HittableList::from_vec(objects.clone());
+            let rays: Vec<_> = (0..2000).map(|_| random_ray(&mut rng, &objects)).collect();
+            let (median, _) = Bvh::build(objects.clone(), BvhStrategy::Median);
+            let (sah, stats) = Bvh::build(objects, BvhStrategy::SAH);
+            assert!(stats.max_leaf_size > 1, "{stats}");
+
+            let mut hits = 0;
+            for ray in &rays {
+                let expected = closest(list.hit(ray, Interval::POSITIVE));
+                hits += expected.is_some() as usize;
+                assert_eq!(closest(median.hit(ray, Interval::POSITIVE)), expected);
+                assert_eq!(closest(sah.hit(ray, Interval::POSITIVE)), expected);
+
+                // and within a shorter interval, which only some of the objects fall in
+                let ray_t = Interval { min: 0.5, max: 6. };
+                let expected = closest(list.hit(ray, ray_t));
+                assert_eq!(closest(median.hit(ray, ray_t)), expected);
+                assert_eq!(closest(sah.hit(ray, ray_t)), expected);
+            }
+            assert!(hits > 1000, "only {hits} rays hit anything");
+        }
+    }
+
+    #[test]
+    fn far_children_can_hold_the_closest_hit() {
+        // the quad's box starts well before the sphere's along x, but the ray only meets the quad
+        // near its far end: whichever way the ray goes, the child visited second has the closer hit
+        let quad: &dyn Hittable = leak(Quad::new(
+            Point::new(0., -1., -1.),
+            Vector::new(10., 0., 2.),
+            Vector::new(0., 2., 0.),
+            &Blank,
+        ));
+        let sphere: &dyn Hittable = leak(Sphere::<Unchanging>::new(
+            Point::new(5.5, 0., 0.),
+            1.,
+            &Blank,
+        ));
+
+        let forward = Ray {
+            origin: Point::new(-5., 0., 0.8),
+            direct: Vector::new(1., 0., 0.),
+            time: 0.,
+        };
+        let backward = Ray {
+            origin: Point::new(20., 0., 0.8),
+            direct: Vector::new(-1., 0., 0.),
+            time: 0.,
+        };
+
+        for strategy in [BvhStrategy::Median, BvhStrategy::Sah { max_leaf_size: 1 }] {
+            let (bvh, stats) = Bvh::build(vec![quad, sphere], strategy);
+            assert_eq!((stats.nodes, stats.leaves), (1, 2));
+            assert_eq!(bvh.nodes[0].axis, 0);
+
+            let hit = bvh.hit(&forward, Interval::POSITIVE).unwrap();
+            assert!((hit.p.x - 4.9).abs() < 1e-4, "{:?}", hit.p);
+            let hit = bvh.hit(&backward, Interval::POSITIVE).unwrap();
+            assert!((hit.p.x - 9.).abs() < 1e-4, "{:?}", hit.p);
+        }
+    }
+
+    #[test]
+    fn empty_bvhs_miss() {
+        let ray = Ray {
+            origin: Point::ZERO,
+            direct: Vector::new(1., 0., 0.),
+            time: 0.,
+        };
+        for strategy in [BvhStrategy::Median, BvhStrategy::SAH] {
+            let bvh = Bvh::new_with_strategy(Vec::new(), strategy);
+            assert!(bvh.hit(&ray, Interval::POSITIVE).is_none());
+        }
     }
 }
@@ -1,5 +1,8 @@
+use glam::{Affine3A, Mat3A, Quat, Vec3};
+
 use crate::{
     ray::Ray,
+    sampler::SampleRng,
     units::{Point, Vector, WorldSpace},
 };
 
@@ -18,6 +21,40 @@ pub struct Rotate<'a, const AXIS: usize = 1> {
     aabb: AABB<f32>,
 }
 
+// An arbitrary affine transform of `object`, built up step by step:
+//
+//     Transform::new(object).scale(Vector::new(1., 2., 1.)).rotate(axis, 30.).translate(offset)
+//
+// applies the steps in the order they are listed.
+pub struct Transform<'a> {
+    object: &'a dyn Hittable,
+    // object to world space, and back
+    matrix: Affine3A,
+    inverse: Affine3A,
+    // the inverse transpose of the linear part, for normals
+    normal_matrix: Mat3A,
+    aabb: AABB<f32>,
+}
+
+// The pose of an object at `time`. It is applied to the object as scale, then rotation, then
+// translation, whatever order the builder methods are called in.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vector,
+    pub rotation: Quat,
+    pub scale: Vector,
+}
+
+// Animates `object` through `keyframes` over the shutter interval, interpolating translation and
+// scale linearly and rotation by slerp. Before the first and after the last keyframe, the object
+// holds still.
+pub struct MotionTransform<'a> {
+    object: &'a dyn Hittable,
+    keyframes: Vec<Keyframe>,
+    aabb: AABB<f32>,
+}
+
 impl<'a> Translate<'a> {
     pub fn new(object: &'a dyn Hittable, offset: Vector) -> Self {
         let aabb = object.bounding_box().offset(offset);
@@ -45,12 +82,20 @@ impl<'a> Hittable for Translate<'a> {
     fn bounding_box(&self) -> AABB<f32> {
         self.aabb
     }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        self.object.pdf_value(origin - self.offset, direction, time)
+    }
+
+    fn random(&self, origin: Point, time: f32, rng: &mut SampleRng) -> Vector {
+        self.object.random(origin - self.offset, time, rng)
+    }
 }
 
 impl<'a, const AXIS: usize> Rotate<'a, AXIS> {
     pub fn new(object: &'a dyn Hittable, angle: f32) -> Self {
         let (sin, cos) = angle.to_radians().sin_cos();
-        let aabb = AABB::EMPTY;
+        let mut aabb = AABB::EMPTY;
 
         let axis1 = (AXIS + 1) % 3;
         let axis2 = (AXIS + 2) % 3;
@@ -60,7 +105,7 @@ impl<'a, const AXIS: usize> Rotate<'a, AXIS> {
                 cos * corner[axis1] - sin * corner[axis2],
                 sin * corner[axis1] + cos * corner[axis2],
             );
-            aabb.insert(corner);
+            aabb = aabb.insert(corner);
         }
 
         Self {
@@ -114,4 +159,625 @@ impl<'a, const AXIS: usize> Hittable for Rotate<'a, AXIS> {
     fn bounding_box(&self) -> AABB<f32> {
         self.aabb
     }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        let origin = self.unrotate(origin);
+        let direction = self.unrotate(direction.to_point()).to_vector();
+        self.object.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: Point, time: f32, rng: &mut SampleRng) -> Vector {
+        let direction = self.object.random(self.unrotate(origin), time, rng);
+        self.rotate(direction.to_point()).to_vector()
+    }
+}
+
+impl<'a> Transform<'a> {
+    pub fn new(object: &'a dyn Hittable) -> Self {
+        Self::with_matrix(object, Affine3A::IDENTITY)
+    }
+
+    pub fn with_matrix(object: &'a dyn Hittable, matrix: Affine3A) -> Self {
+        let inverse = matrix.inverse();
+        let normal_matrix = inverse.matrix3.transpose();
+
+        // the box around the transformed corners; an empty box has no corners to transform
+        let bounds = object.bounding_box();
+        let aabb = if (0..3).any(|axis| bounds[axis].min > bounds[axis].max) {
+            AABB::EMPTY
+        } else {
+            bounds
+                .to_corners::<WorldSpace>()
+                .into_iter()
+                .fold(AABB::EMPTY, |aabb, corner| {
+                    aabb.insert(transform_point(&matrix, corner))
+                })
+        };
+
+        Self {
+            object,
+            matrix,
+            inverse,
+            normal_matrix,
+            aabb,
+        }
+    }
+
+    pub fn matrix(&self) -> Affine3A {
+        self.matrix
+    }
+
+    // the following apply another step after the ones so far
+
+    pub fn then(self, matrix: Affine3A) -> Self {
+        Self::with_matrix(self.object, matrix * self.matrix)
+    }
+
+    pub fn translate(self, offset: Vector) -> Self {
+        self.then(Affine3A::from_translation(to_glam(offset)))
+    }
+
+    // by `angle` degrees, counterclockwise when looking down `axis`
+    pub fn rotate(self, axis: Vector, angle: f32) -> Self {
+        let axis = to_glam(axis.normalize());
+        self.then(Affine3A::from_axis_angle(axis, angle.to_radians()))
+    }
+
+    pub fn scale(self, scale: Vector) -> Self {
+        self.then(Affine3A::from_scale(to_glam(scale)))
+    }
+}
+
+impl<'a> Hittable for Transform<'a> {
+    fn hit(&self, ray: &Ray, ray_t: Interval<f32>) -> Option<HitRecord> {
+        // the direction isn't renormalised, so distances along the ray stay the same
+        let new_ray = Ray {
+            origin: transform_point(&self.inverse, ray.origin),
+            direct: transform_vector(&self.inverse, ray.direct),
+            ..*ray
+        };
+
+        self.object.hit(&new_ray, ray_t).map(|mut rec| {
+            rec.p = transform_point(&self.matrix, rec.p);
+            rec.normal = from_glam(self.normal_matrix.mul_vec3(to_glam(rec.normal))).normalize();
+            rec
+        })
+    }
+
+    fn bounding_box(&self) -> AABB<f32> {
+        self.aabb
+    }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        let local = transform_vector(&self.inverse, direction).normalize();
+        let pdf = self
+            .object
+            .pdf_value(transform_point(&self.inverse, origin), local, time);
+
+        // a linear map A stretches solid angle around the unit direction u by |det A| / |A u|^3
+        let stretch = transform_vector(&self.matrix, local).length();
+        pdf * stretch.powi(3) / self.matrix.matrix3.determinant().abs()
+    }
+
+    fn random(&self, origin: Point, time: f32, rng: &mut SampleRng) -> Vector {
+        let origin = transform_point(&self.inverse, origin);
+        let direction = self.object.random(origin, time, rng);
+        transform_vector(&self.matrix, direction)
+    }
+}
+
+impl Keyframe {
+    pub fn new(time: f32) -> Self {
+        Self {
+            time,
+            translation: Vector::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vector::ONE,
+        }
+    }
+
+    pub fn translate(mut self, offset: Vector) -> Self {
+        self.translation += offset;
+        self
+    }
+
+    // by `angle` degrees, counterclockwise when looking down `axis`
+    pub fn rotate(mut self, axis: Vector, angle: f32) -> Self {
+        let rotation = Quat::from_axis_angle(to_glam(axis.normalize()), angle.to_radians());
+        self.rotation = rotation * self.rotation;
+        self
+    }
+
+    pub fn scale(mut self, scale: Vector) -> Self {
+        self.scale *= scale;
+        self
+    }
+
+    fn lerp(&self, other: &Self, time: f32) -> Self {
+        let s = (time - self.time) / (other.time - self.time);
+        Self {
+            time,
+            translation: self.translation + (other.translation - self.translation) * s,
+            rotation: self.rotation.slerp(other.rotation, s),
+            scale: self.scale + (other.scale - self.scale) * s,
+        }
+    }
+
+    fn point_to_world(&self, point: Point) -> Point {
+        self.rotate_to_world(point.to_vector() * self.scale)
+            .to_point()
+            + self.translation
+    }
+
+    fn vector_to_world(&self, vector: Vector) -> Vector {
+        self.rotate_to_world(vector * self.scale)
+    }
+
+    // the inverse transpose of rotating after scaling is rotating after unscaling
+    fn normal_to_world(&self, normal: Vector) -> Vector {
+        self.rotate_to_world(normal / self.scale).normalize()
+    }
+
+    fn point_to_object(&self, point: Point) -> Point {
+        self.vector_to_object(point.to_vector() - self.translation)
+            .to_point()
+    }
+
+    fn vector_to_object(&self, vector: Vector) -> Vector {
+        from_glam(self.rotation.inverse().mul_vec3(to_glam(vector))) / self.scale
+    }
+
+    fn rotate_to_world(&self, vector: Vector) -> Vector {
+        from_glam(self.rotation.mul_vec3(to_glam(vector)))
+    }
+}
+
+impl<'a> MotionTransform<'a> {
+    // steps taken through each pair of keyframes to bound the motion in between
+    const BOUNDS_STEPS: usize = 16;
+
+    pub fn new(object: &'a dyn Hittable, mut keyframes: Vec<Keyframe>) -> Self {
+        if keyframes.is_empty() {
+            keyframes.push(Keyframe::new(0.));
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let bounds = object.bounding_box();
+        let aabb = if (0..3).any(|axis| bounds[axis].min > bounds[axis].max) {
+            AABB::EMPTY
+        } else {
+            Self::motion_bounds(bounds, &keyframes)
+        };
+
+        Self {
+            object,
+            keyframes,
+            aabb,
+        }
+    }
+
+    fn at_time(&self, time: f32) -> Keyframe {
+        let next = self.keyframes.partition_point(|key| key.time <= time);
+        match next {
+            0 => self.keyframes[0],
+            n if n == self.keyframes.len() => self.keyframes[n - 1],
+            n => self.keyframes[n - 1].lerp(&self.keyframes[n], time),
+        }
+    }
+
+    // Translation and scale move the corners of `bounds` along straight lines, but rotation
+    // sweeps them along arcs, which can bulge out between the sampled poses by at most the
+    // sagitta r (1 - cos(θ / 2)) of the angle θ turned in one step.
+    fn motion_bounds(bounds: AABB<f32>, keyframes: &[Keyframe]) -> AABB<f32> {
+        let corners = bounds.to_corners::<WorldSpace>();
+        let mut aabb = AABB::EMPTY;
+        let add_pose = |aabb: AABB<f32>, key: &Keyframe| {
+            corners.iter().fold(aabb, |aabb, &corner| {
+                aabb.insert(key.point_to_world(corner))
+            })
+        };
+
+        aabb = add_pose(aabb, &keyframes[0]);
+        for pair in keyframes.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if b.time <= a.time {
+                aabb = add_pose(aabb, b);
+                continue;
+            }
+
+            let mut radius: f32 = 0.;
+            for step in 1..=Self::BOUNDS_STEPS {
+                let time = a.time + (b.time - a.time) * step as f32 / Self::BOUNDS_STEPS as f32;
+                let key = a.lerp(b, time);
+                aabb = add_pose(aabb, &key);
+                for corner in corners {
+                    radius = radius.max((corner.to_vector() * key.scale).length());
+                }
+            }
+
+            let step_angle = a.rotation.angle_between(b.rotation) / Self::BOUNDS_STEPS as f32;
+            let sagitta = radius * (1. - (step_angle / 2.).cos());
+            aabb = aabb.expand(2. * sagitta);
+        }
+
+        aabb
+    }
+}
+
+impl<'a> Hittable for MotionTransform<'a> {
+    fn hit(&self, ray: &Ray, ray_t: Interval<f32>) -> Option<HitRecord> {
+        let key = self.at_time(ray.time);
+        let new_ray = Ray {
+            origin: key.point_to_object(ray.origin),
+            direct: key.vector_to_object(ray.direct),
+            ..*ray
+        };
+
+        self.object.hit(&new_ray, ray_t).map(|mut rec| {
+            rec.p = key.point_to_world(rec.p);
+            rec.normal = key.normal_to_world(rec.normal);
+            rec
+        })
+    }
+
+    fn bounding_box(&self) -> AABB<f32> {
+        self.aabb
+    }
+
+    // as for `Transform`, with the pose at `time`
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        let key = self.at_time(time);
+        let local = key.vector_to_object(direction).normalize();
+        let pdf = self
+            .object
+            .pdf_value(key.point_to_object(origin), local, time);
+
+        let stretch = key.vector_to_world(local).length();
+        let det = key.scale.x * key.scale.y * key.scale.z;
+        pdf * stretch.powi(3) / det.abs()
+    }
+
+    fn random(&self, origin: Point, time: f32, rng: &mut SampleRng) -> Vector {
+        let key = self.at_time(time);
+        let direction = self.object.random(key.point_to_object(origin), time, rng);
+        key.vector_to_world(direction)
+    }
+}
+
+fn transform_point(matrix: &Affine3A, point: Point) -> Point {
+    from_glam(matrix.transform_point3(to_glam(point.to_vector()))).to_point()
+}
+
+fn transform_vector(matrix: &Affine3A, vector: Vector) -> Vector {
+    from_glam(matrix.transform_vector3(to_glam(vector)))
+}
+
+fn to_glam(v: Vector) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
+
+fn from_glam(v: Vec3) -> Vector {
+    Vector::new(v.x, v.y, v.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        hittable::{quad::Quad, HittableList, Sphere},
+        material::Material,
+        time_utils::Unchanging,
+        units::RenderRng,
+    };
+
+    use super::*;
+
+    struct Blank;
+
+    impl Material for Blank {}
+
+    const EPSILON: f32 = 1e-3;
+
+    fn leak<T>(x: T) -> &'static T {
+        Box::leak(Box::new(x))
+    }
+
+    fn unit_box(min: Point, max: Point) -> &'static dyn Hittable {
+        let faces = Quad::new_box(min, max, &Blank)
+            .into_iter()
+            .map(|face| leak(face) as &dyn Hittable)
+            .collect();
+        leak(HittableList::from_vec(faces))
+    }
+
+    fn sphere(center: Point, radius: f32) -> &'static dyn Hittable {
+        leak(Sphere::<Unchanging>::new(center, radius, &Blank))
+    }
+
+    // the same rotation `Rotate::<AXIS>` applies
+    fn rotated(mut point: Point, axis: usize, angle: f32) -> Point {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let (axis1, axis2) = ((axis + 1) % 3, (axis + 2) % 3);
+        (point[axis1], point[axis2]) = (
+            cos * point[axis1] - sin * point[axis2],
+            sin * point[axis1] + cos * point[axis2],
+        );
+        point
+    }
+
+    fn rotate_by_axis(object: &'static dyn Hittable, axis: usize, angle: f32) -> Box<dyn Hittable> {
+        match axis {
+            0 => Box::new(Rotate::<0>::new(object, angle)),
+            1 => Box::new(Rotate::<1>::new(object, angle)),
+            _ => Box::new(Rotate::<2>::new(object, angle)),
+        }
+    }
+
+    fn assert_contains(aabb: AABB<f32>, point: Point) {
+        for axis in 0..3 {
+            assert!(
+                aabb[axis].expand(2. * EPSILON).contains(point[axis]),
+                "{point:?} is outside of {:?} along axis {axis}",
+                aabb[axis],
+            );
+        }
+    }
+
+    fn close(a: Vector, b: Vector) -> bool {
+        (a - b).length() < EPSILON * a.length().max(1.)
+    }
+
+    fn assert_same_hits(transformed: &dyn Hittable, expected: &dyn Hittable, target: Point) {
+        let mut rng = RenderRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let origin = target
+                + Vector::new(
+                    rng.gen_range(-20.0..20.0),
+                    rng.gen_range(-20.0..20.0),
+                    rng.gen_range(-20.0..20.0),
+                );
+            let aim = target
+                + Vector::new(
+                    rng.gen_range(-2.0..2.0),
+                    rng.gen_range(-2.0..2.0),
+                    rng.gen_range(-2.0..2.0),
+                );
+            let ray = Ray {
+                origin,
+                direct: aim - origin,
+                time: 0.,
+            };
+
+            let got = transformed.hit(&ray, Interval::<f32>::POSITIVE);
+            let want = expected.hit(&ray, Interval::<f32>::POSITIVE);
+            match (got, want) {
+                (None, None) => {}
+                (Some(got), Some(want)) => {
+                    // a BVH would cull the object otherwise
+                    assert!(transformed
+                        .bounding_box()
+                        .hit(&ray, Interval::<f32>::POSITIVE));
+                    assert!((got.t - want.t).abs() < EPSILON, "{} != {}", got.t, want.t);
+                    assert!(close(got.p.to_vector(), want.p.to_vector()));
+                    assert!(close(got.normal, want.normal));
+                    assert_eq!(got.front_face, want.front_face);
+                }
+                (got, want) => panic!(
+                    "hit mismatch for {ray:?}: got {:?}, expected {:?}",
+                    got.map(|hit| hit.t),
+                    want.map(|hit| hit.t)
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_box_contains_rotated_corners() {
+        let (min, max) = (Point::new(-1., 0., 2.), Point::new(3., 5., 4.));
+        let object = unit_box(min, max);
+        let corners = object.bounding_box().to_corners::<WorldSpace>();
+
+        for axis in 0..3 {
+            for angle in [0., 15., -18., 45., 90., 137., 180., 270.] {
+                let rotate = rotate_by_axis(object, axis, angle);
+                let aabb = rotate.bounding_box();
+                for axis in 0..3 {
+                    assert!(aabb[axis].size() > 0., "empty bounding box");
+                }
+                for corner in corners {
+                    assert_contains(aabb, rotated(corner, axis, angle));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn translated_box_contains_translated_corners() {
+        let object = unit_box(Point::new(0., 0., 0.), Point::new(165., 330., 165.));
+        let offset = Vector::new(265., -10., 295.);
+        let translate = Translate::new(object, offset);
+
+        let aabb = translate.bounding_box();
+        for corner in object.bounding_box().to_corners::<WorldSpace>() {
+            assert_contains(aabb, corner + offset);
+        }
+        assert!((aabb.x.min - 265.).abs() < EPSILON && (aabb.y.max - 320.).abs() < EPSILON);
+    }
+
+    #[test]
+    fn rotate_then_translate_contains_corners() {
+        let object = unit_box(Point::ZERO, Point::splat(165.));
+        let offset = Vector::new(130., 0., 65.);
+        let rotate = Rotate::<1>::new(object, -18.);
+        let translate = Translate::new(&rotate, offset);
+
+        for corner in object.bounding_box().to_corners::<WorldSpace>() {
+            assert_contains(translate.bounding_box(), rotated(corner, 1, -18.) + offset);
+        }
+    }
+
+    #[test]
+    fn transform_box_contains_transformed_corners() {
+        let object = unit_box(Point::new(-1., -1., -1.), Point::new(1., 2., 3.));
+        let transform = Transform::new(object)
+            .scale(Vector::new(2., 0.5, 1.))
+            .rotate(Vector::new(1., 1., 0.), 33.)
+            .translate(Vector::new(4., -2., 7.));
+
+        let aabb = transform.bounding_box();
+        for corner in object.bounding_box().to_corners::<WorldSpace>() {
+            let corner = transform_point(&transform.matrix(), corner);
+            assert_contains(aabb, corner);
+        }
+    }
+
+    #[test]
+    fn rotated_hits_match_rotated_geometry() {
+        let center = Point::new(3., 1., -2.);
+        let object = sphere(center, 1.5);
+
+        for axis in 0..3 {
+            for angle in [30., -75., 180.] {
+                let rotate = rotate_by_axis(object, axis, angle);
+                let center = rotated(center, axis, angle);
+                assert_same_hits(rotate.as_ref(), sphere(center, 1.5), center);
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_box_hits_match_rotated_quads() {
+        let object = unit_box(Point::ZERO, Point::new(2., 3., 1.));
+        let rotate = Rotate::<1>::new(object, 90.);
+
+        // turning the box a quarter around y maps x to -z and z to x
+        let expected = unit_box(Point::new(0., 0., -2.), Point::new(1., 3., 0.));
+        assert_same_hits(&rotate, expected, Point::new(0.5, 1.5, -1.));
+    }
+
+    #[test]
+    fn translated_hits_match_translated_geometry() {
+        let object = sphere(Point::new(0., 0., 0.), 2.);
+        let offset = Vector::new(5., -3., 10.);
+        let translate = Translate::new(object, offset);
+
+        let center = Point::ZERO + offset;
+        assert_same_hits(&translate, sphere(center, 2.), center);
+
+        let object = unit_box(Point::ZERO, Point::splat(2.));
+        let translate = Translate::new(object, offset);
+        let expected = unit_box(center, center + Vector::splat(2.));
+        assert_same_hits(&translate, expected, center + Vector::splat(1.));
+    }
+
+    #[test]
+    fn transform_hits_match_rotate_and_translate() {
+        let object = unit_box(Point::ZERO, Point::new(165., 330., 165.));
+        let offset = Vector::new(265., 0., 295.);
+        let rotate = Rotate::<1>::new(object, 15.);
+        let nested = Translate::new(&rotate, offset);
+        let transform = Transform::new(object)
+            .rotate(Vector::new(0., 1., 0.), 15.)
+            .translate(offset);
+
+        assert_same_hits(&transform, &nested, Point::new(350., 165., 380.));
+    }
+
+    fn motion_keyframes() -> Vec<Keyframe> {
+        vec![
+            Keyframe::new(0.),
+            Keyframe::new(0.5)
+                .scale(Vector::new(1., 2., 1.))
+                .rotate(Vector::new(0., 1., 0.), 170.)
+                .translate(Vector::new(3., 0., 0.)),
+            Keyframe::new(1.)
+                .rotate(Vector::new(1., 0., 1.), -90.)
+                .translate(Vector::new(3., 4., -2.)),
+        ]
+    }
+
+    #[test]
+    fn motion_box_contains_every_pose() {
+        let object = unit_box(Point::new(1., 0., 0.), Point::new(3., 1., 2.));
+        let motion = MotionTransform::new(object, motion_keyframes());
+
+        let aabb = motion.bounding_box();
+        for i in 0..=1000 {
+            let key = motion.at_time(i as f32 / 1000.);
+            for corner in object.bounding_box().to_corners::<WorldSpace>() {
+                assert_contains(aabb, key.point_to_world(corner));
+            }
+        }
+    }
+
+    #[test]
+    fn motion_hits_match_transform_at_each_time() {
+        let object = unit_box(Point::new(-1., -1., -1.), Point::new(1., 2., 1.));
+        let motion = MotionTransform::new(object, motion_keyframes());
+
+        for time in [0., 0.2, 0.5, 0.9, 1.] {
+            let key = motion.at_time(time);
+            let matrix = Affine3A::from_scale_rotation_translation(
+                to_glam(key.scale),
+                key.rotation,
+                to_glam(key.translation),
+            );
+            let transform = Transform::with_matrix(object, matrix);
+
+            let target = key.point_to_world(Point::ZERO);
+            let mut rng = RenderRng::seed_from_u64(3);
+            for _ in 0..100 {
+                let origin = target + random_offset(&mut rng, 10.);
+                let ray = Ray {
+                    origin,
+                    direct: target + random_offset(&mut rng, 1.) - origin,
+                    time,
+                };
+                let got = motion.hit(&ray, Interval::<f32>::POSITIVE).map(|hit| hit.t);
+                let want = transform
+                    .hit(&ray, Interval::<f32>::POSITIVE)
+                    .map(|hit| hit.t);
+                match (got, want) {
+                    (Some(got), Some(want)) => assert!((got - want).abs() < EPSILON),
+                    (got, want) => assert_eq!(got.is_some(), want.is_some()),
+                }
+            }
+        }
+    }
+
+    fn random_offset(rng: &mut RenderRng, size: f32) -> Vector {
+        Vector::new(
+            rng.gen_range(-size..size),
+            rng.gen_range(-size..size),
+            rng.gen_range(-size..size),
+        )
+    }
+
+    #[test]
+    fn scaled_sphere_normals_use_inverse_transpose() {
+        // squashing a sphere into an ellipsoid; at (0, 0.5, 0) the normal still points up, and
+        // at the equator it still points outwards
+        let transform = Transform::new(sphere(Point::ZERO, 1.)).scale(Vector::new(2., 0.5, 1.));
+        let down = Ray {
+            origin: Point::new(0., 5., 0.),
+            direct: Vector::new(0., -1., 0.),
+            time: 0.,
+        };
+        let hit = transform.hit(&down, Interval::<f32>::POSITIVE).unwrap();
+        assert!((hit.t - 4.5).abs() < EPSILON);
+        assert!(close(hit.normal, Vector::new(0., 1., 0.)));
+
+        // a point on the ellipse x^2/4 + y^2/0.25 = 1 in the xy plane, and its analytic normal
+        let (x, y) = (2. * 0.6, 0.5 * 0.8);
+        let normal = Vector::new(x / 4., y / 0.25, 0.).normalize();
+        let ray = Ray {
+            origin: Point::new(x, y, 0.) + normal * 3.,
+            direct: -normal,
+            time: 0.,
+        };
+        let hit = transform.hit(&ray, Interval::<f32>::POSITIVE).unwrap();
+        assert!((hit.t - 3.).abs() < EPSILON);
+        assert!(close(hit.normal, normal));
+    }
 }
@@ -0,0 +1,119 @@
+use core::alloc::Allocator;
+
+use crate::{
+    material::Material,
+    ray::Ray,
+    units::{Point, TexCoord, Vector},
+};
+
+use super::{
+    bvh::Bvh,
+    triangle::{interpolate_normal, interpolate_uv, intersect, triangle_bounds},
+    HitRecord, Hittable, Interval, AABB,
+};
+
+// Indices of one triangle's corners into the position, normal and texture coordinate buffers of
+// a `MeshData`. Normals and texture coordinates are optional per face.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshFace {
+    pub positions: [u32; 3],
+    pub normals: Option<[u32; 3]>,
+    pub uvs: Option<[u32; 3]>,
+}
+
+#[derive(Default)]
+pub struct MeshData {
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vector>,
+    pub uvs: Vec<TexCoord>,
+    pub faces: Vec<MeshFace>,
+}
+
+pub struct TriangleMesh<'a> {
+    data: &'a MeshData,
+    root: &'a dyn Hittable,
+}
+
+struct MeshTriangle<'a> {
+    data: &'a MeshData,
+    face: MeshFace,
+    material: &'a dyn Material,
+    aabb: AABB<f32>,
+}
+
+impl MeshData {
+    pub fn face_vertices(&self, face: &MeshFace) -> [Point; 3] {
+        face.positions.map(|i| self.positions[i as usize])
+    }
+}
+
+impl<'a> TriangleMesh<'a> {
+    pub fn new<A: Allocator + Copy + 'a>(
+        data: MeshData,
+        material: &'a dyn Material,
+        alloc: A,
+    ) -> Self {
+        let data: &'a MeshData = Box::leak(Box::new_in(data, alloc));
+
+        let mut triangles = Vec::with_capacity_in(data.faces.len(), alloc);
+        triangles.extend(data.faces.iter().map(|&face| MeshTriangle {
+            data,
+            face,
+            material,
+            aabb: triangle_bounds(data.face_vertices(&face)),
+        }));
+        let triangles: &'a [MeshTriangle<'a>] = triangles.leak();
+
+        let objects: Vec<&'a dyn Hittable> = triangles
+            .iter()
+            .map(|triangle| triangle as &dyn Hittable)
+            .collect();
+
+        let root = Box::leak(Box::new_in(Bvh::new(objects), alloc));
+
+        Self { data, root }
+    }
+
+    pub fn data(&self) -> &'a MeshData {
+        self.data
+    }
+}
+
+impl Hittable for TriangleMesh<'_> {
+    fn hit(&self, ray: &Ray, ray_t: Interval<f32>) -> Option<HitRecord> {
+        self.root.hit(ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> AABB<f32> {
+        self.root.bounding_box()
+    }
+}
+
+impl Hittable for MeshTriangle<'_> {
+    fn hit(&self, ray: &Ray, ray_t: Interval<f32>) -> Option<HitRecord> {
+        let vertices = self.data.face_vertices(&self.face);
+        let (t, bary) = intersect(vertices, ray, ray_t)?;
+
+        let [a, b, c] = vertices;
+        let normal = (b - a).cross(c - a).normalize();
+
+        let uv = match self.face.uvs {
+            Some(uvs) => interpolate_uv(uvs.map(|i| self.data.uvs[i as usize]), bary),
+            None => TexCoord::new(bary[1], bary[2]),
+        };
+
+        let rec = HitRecord::new(ray, ray.at(t), normal, uv, self.material, t);
+
+        Some(match self.face.normals {
+            Some(normals) => rec.with_shading_normal(interpolate_normal(
+                normals.map(|i| self.data.normals[i as usize]),
+                bary,
+            )),
+            None => rec,
+        })
+    }
+
+    fn bounding_box(&self) -> AABB<f32> {
+        self.aabb
+    }
+}
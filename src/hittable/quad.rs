@@ -1,8 +1,10 @@
 use glamour::Point2;
+use rand::Rng;
 
 use crate::{
     material::Material,
     ray::Ray,
+    sampler::SampleRng,
     units::{Point, Vector},
 };
 
@@ -16,6 +18,7 @@ pub struct Quad<'a> {
     pub normal: Vector,
     pub d: f32,
     pub w: Vector,
+    pub area: f32,
 
     pub material: &'a dyn Material,
     pub aabb: AABB<f32>,
@@ -27,8 +30,12 @@ impl<'a> Quad<'a> {
         let normal = n.normalize();
         let d = normal.dot(q.to_vector());
         let w = n / n.dot(n);
+        let area = n.length();
 
-        let aabb = AABB::<f32>::from_corners(q, q + u + v).pad();
+        // both diagonals, since a skewed quad reaches past the box around either one
+        let aabb = AABB::<f32>::from_corners(q, q + u + v)
+            .combine(AABB::from_corners(q + u, q + v))
+            .pad();
 
         Self {
             q,
@@ -37,6 +44,7 @@ impl<'a> Quad<'a> {
             normal,
             d,
             w,
+            area,
             material,
             aabb,
         }
@@ -115,4 +123,26 @@ impl<'a> Hittable for Quad<'a> {
     fn bounding_box(&self) -> AABB<f32> {
         self.aabb
     }
+
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        let ray = Ray {
+            origin,
+            direct: direction,
+            time,
+        };
+        let Some(hit) = self.hit(&ray, Interval::<f32>::POSITIVE) else {
+            return 0.0;
+        };
+
+        // convert the uniform density over the area into one over solid angle
+        let distance_squared = hit.t * hit.t * direction.length_squared();
+        let cosine = (direction.dot(self.normal) / direction.length()).abs();
+
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: Point, _time: f32, rng: &mut SampleRng) -> Vector {
+        let p = self.q + self.u * rng.gen::<f32>() + self.v * rng.gen::<f32>();
+        p - origin
+    }
 }
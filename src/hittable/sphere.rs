@@ -1,10 +1,13 @@
 use std::f32::consts::PI;
 
+use rand::Rng;
+
 use crate::{
     material::Material,
     ray::Ray,
+    sampler::SampleRng,
     time_utils::Movement,
-    units::{Point, TexCoord, Vector},
+    units::{orthonormal_basis, random_unit_vector, Point, TexCoord, Vector},
 };
 
 use super::{HitRecord, Hittable, Interval, AABB};
@@ -39,6 +42,46 @@ impl<Center: Movement<Point>> Hittable for Sphere<'_, Center> {
     fn bounding_box(&self) -> AABB<f32> {
         self.aabb
     }
+
+    // samples the cone of directions subtended by the sphere, or every direction from inside it
+    fn pdf_value(&self, origin: Point, direction: Vector, time: f32) -> f32 {
+        let ray = Ray {
+            origin,
+            direct: direction,
+            time,
+        };
+        if self.hit(&ray, Interval::<f32>::POSITIVE).is_none() {
+            return 0.0;
+        }
+
+        let center = <Center as Movement<_>>::get_at_time(&self.center, time);
+        let distance_squared = (center - origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * PI);
+        }
+
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        solid_angle.recip()
+    }
+
+    fn random(&self, origin: Point, time: f32, rng: &mut SampleRng) -> Vector {
+        let center = <Center as Movement<_>>::get_at_time(&self.center, time);
+        let direction = center - origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return random_unit_vector(rng);
+        }
+
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let z = 1.0 + rng.gen::<f32>() * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        let [u, v, w] = orthonormal_basis(direction);
+        u * (phi.cos() * sin_theta) + v * (phi.sin() * sin_theta) + w * z
+    }
 }
 
 impl<Center: Movement<Point>> Sphere<'_, Center> {
@@ -0,0 +1,126 @@
+use crate::{
+    material::Material,
+    ray::Ray,
+    units::{Point, TexCoord, Vector},
+};
+
+use super::{HitRecord, Hittable, Interval, AABB};
+
+pub struct Triangle<'a> {
+    pub vertices: [Point; 3],
+    pub normals: Option<[Vector; 3]>,
+    pub uvs: [TexCoord; 3],
+
+    pub normal: Vector,
+    pub material: &'a dyn Material,
+    pub aabb: AABB<f32>,
+}
+
+impl<'a> Triangle<'a> {
+    pub fn new(a: Point, b: Point, c: Point, material: &'a dyn Material) -> Self {
+        let uvs = [
+            TexCoord::new(0.0, 0.0),
+            TexCoord::new(1.0, 0.0),
+            TexCoord::new(0.0, 1.0),
+        ];
+
+        Self::with_attributes([a, b, c], None, uvs, material)
+    }
+
+    pub fn with_attributes(
+        vertices: [Point; 3],
+        normals: Option<[Vector; 3]>,
+        uvs: [TexCoord; 3],
+        material: &'a dyn Material,
+    ) -> Self {
+        let [a, b, c] = vertices;
+        let normal = (b - a).cross(c - a).normalize();
+        let aabb = triangle_bounds(vertices);
+
+        Self {
+            vertices,
+            normals,
+            uvs,
+            normal,
+            material,
+            aabb,
+        }
+    }
+}
+
+impl<'a> Hittable for Triangle<'a> {
+    fn hit(&self, ray: &Ray, ray_t: Interval<f32>) -> Option<HitRecord> {
+        let (t, bary) = intersect(self.vertices, ray, ray_t)?;
+
+        let mut rec = HitRecord::new(
+            ray,
+            ray.at(t),
+            self.normal,
+            interpolate_uv(self.uvs, bary),
+            self.material,
+            t,
+        );
+        if let Some(normals) = self.normals {
+            rec = rec.with_shading_normal(interpolate_normal(normals, bary));
+        }
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> AABB<f32> {
+        self.aabb
+    }
+}
+
+pub(crate) fn triangle_bounds([a, b, c]: [Point; 3]) -> AABB<f32> {
+    AABB::<f32>::from_corners(a, b).insert(c).pad()
+}
+
+// Möller–Trumbore intersection, returning the ray parameter and the barycentric weights of the
+// three vertices
+pub(crate) fn intersect(
+    [a, b, c]: [Point; 3],
+    ray: &Ray,
+    ray_t: Interval<f32>,
+) -> Option<(f32, [f32; 3])> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+
+    let pvec = ray.direct.cross(edge2);
+    // `det` scales with the triangle's area and the ray's length, so any fixed epsilon would miss
+    // small triangles; only rays exactly parallel to the plane are left out
+    let inv_det = edge1.dot(pvec).recip();
+    if !inv_det.is_finite() {
+        return None;
+    }
+
+    let tvec = ray.origin - a;
+    let u = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(edge1);
+    let v = ray.direct.dot(qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(qvec) * inv_det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+
+    Some((t, [1.0 - u - v, u, v]))
+}
+
+pub(crate) fn interpolate_uv(uvs: [TexCoord; 3], [w0, w1, w2]: [f32; 3]) -> TexCoord {
+    TexCoord::new(
+        w0 * uvs[0].x + w1 * uvs[1].x + w2 * uvs[2].x,
+        w0 * uvs[0].y + w1 * uvs[1].y + w2 * uvs[2].y,
+    )
+}
+
+pub(crate) fn interpolate_normal(normals: [Vector; 3], [w0, w1, w2]: [f32; 3]) -> Vector {
+    (normals[0] * w0 + normals[1] * w1 + normals[2] * w2).normalize()
+}
@@ -1,9 +1,18 @@
 #![feature(allocator_api)]
 
+pub mod aov;
 pub mod camera;
+pub mod film;
+pub mod filter;
 pub mod hittable;
+pub mod loader;
 pub mod material;
+pub mod output;
+pub mod progress;
 pub mod ray;
+pub mod sampler;
 pub mod texture;
+pub mod tiles;
 pub mod time_utils;
+pub mod tonemap;
 pub mod units;
@@ -0,0 +1,44 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+pub mod json;
+pub mod obj;
+pub mod scene;
+
+#[derive(Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl LoadError {
+    pub fn new<P: AsRef<Path>, M: Into<String>>(path: P, line: Option<usize>, message: M) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub fn at_line<P: AsRef<Path>, M: Into<String>>(path: P, line: usize, message: M) -> Self {
+        Self::new(path, Some(line), message)
+    }
+
+    pub fn io<P: AsRef<Path>>(path: P, err: std::io::Error) -> Self {
+        Self::new(path, None, err.to_string())
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
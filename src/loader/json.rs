@@ -0,0 +1,470 @@
+use std::fmt;
+
+// A minimal JSON reader for scene files. Every value remembers the line it started on, so that
+// the scene loader can point at the offending line when a value has the wrong shape.
+#[derive(Clone, Debug)]
+pub struct Value {
+    pub json: Json,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Debug)]
+pub struct JsonError {
+    pub line: usize,
+    pub message: String,
+}
+
+pub type JsonResult<T> = Result<T, JsonError>;
+
+impl JsonError {
+    pub fn new<M: Into<String>>(line: usize, message: M) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// how deeply arrays and objects can nest, so that a malicious file can't overflow the stack
+const MAX_DEPTH: usize = 128;
+
+pub fn parse(source: &str) -> JsonResult<Value> {
+    let mut parser = Parser {
+        bytes: source.as_bytes(),
+        pos: 0,
+        line: 1,
+        depth: 0,
+    };
+
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.bytes.len() {
+        return Err(parser.error("trailing characters after the top-level value"));
+    }
+
+    Ok(value)
+}
+
+impl Json {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+impl Value {
+    fn expected<T>(&self, what: &str) -> JsonResult<T> {
+        Err(JsonError::new(
+            self.line,
+            format!("expected {what}, found {}", self.json.type_name()),
+        ))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match &self.json {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn field(&self, key: &str) -> JsonResult<&Value> {
+        self.as_object()?;
+        self.get(key)
+            .ok_or_else(|| JsonError::new(self.line, format!("missing field `{key}`")))
+    }
+
+    // rejects fields outside of `allowed`, to catch typos in hand-written files
+    pub fn check_fields(&self, allowed: &[&str]) -> JsonResult<()> {
+        for (key, value) in self.as_object()? {
+            if !allowed.contains(&key.as_str()) {
+                return Err(JsonError::new(
+                    value.line,
+                    format!(
+                        "unknown field `{key}` (expected one of: {})",
+                        allowed.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn as_object(&self) -> JsonResult<&[(String, Value)]> {
+        match &self.json {
+            Json::Object(fields) => Ok(fields),
+            _ => self.expected("an object"),
+        }
+    }
+
+    pub fn as_array(&self) -> JsonResult<&[Value]> {
+        match &self.json {
+            Json::Array(values) => Ok(values),
+            _ => self.expected("an array"),
+        }
+    }
+
+    pub fn as_str(&self) -> JsonResult<&str> {
+        match &self.json {
+            Json::String(s) => Ok(s),
+            _ => self.expected("a string"),
+        }
+    }
+
+    pub fn as_bool(&self) -> JsonResult<bool> {
+        match self.json {
+            Json::Bool(b) => Ok(b),
+            _ => self.expected("a boolean"),
+        }
+    }
+
+    pub fn as_f64(&self) -> JsonResult<f64> {
+        match self.json {
+            Json::Number(n) => Ok(n),
+            _ => self.expected("a number"),
+        }
+    }
+
+    pub fn as_f32(&self) -> JsonResult<f32> {
+        self.as_f64().map(|n| n as f32)
+    }
+
+    pub fn as_u64(&self) -> JsonResult<u64> {
+        let n = self.as_f64()?;
+        if n < 0.0 || n.fract() != 0.0 || n > u64::MAX as f64 {
+            return self.expected("a non-negative integer");
+        }
+
+        Ok(n as u64)
+    }
+
+    pub fn as_usize(&self) -> JsonResult<usize> {
+        self.as_u64().map(|n| n as usize)
+    }
+
+    pub fn as_f32_array<const N: usize>(&self) -> JsonResult<[f32; N]> {
+        let values = self.as_array()?;
+        if values.len() != N {
+            return Err(JsonError::new(
+                self.line,
+                format!("expected {N} numbers, found {}", values.len()),
+            ));
+        }
+
+        let mut out = [0.0; N];
+        for (out, value) in out.iter_mut().zip(values) {
+            *out = value.as_f32()?;
+        }
+
+        Ok(out)
+    }
+}
+
+struct Parser<'s> {
+    bytes: &'s [u8],
+    pos: usize,
+    line: usize,
+    // how many arrays and objects the current value is in
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error<M: Into<String>>(&self, message: M) -> JsonError {
+        JsonError::new(self.line, message)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        if byte == b'\n' {
+            self.line += 1;
+        }
+        Some(byte)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\r' | b'\n') = self.peek() {
+            self.bump();
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> JsonResult<()> {
+        self.skip_whitespace();
+        match self.bump() {
+            Some(b) if b == byte => Ok(()),
+            Some(b) => Err(self.error(format!(
+                "expected `{}`, found `{}`",
+                byte as char, b as char
+            ))),
+            None => Err(self.error(format!("expected `{}`, found end of file", byte as char))),
+        }
+    }
+
+    fn value(&mut self) -> JsonResult<Value> {
+        self.skip_whitespace();
+        let line = self.line;
+
+        let json = match self.peek() {
+            None => return Err(self.error("unexpected end of file")),
+            Some(b'{' | b'[') if self.depth == MAX_DEPTH => {
+                return Err(self.error(format!("values nested more than {MAX_DEPTH} deep")));
+            }
+            Some(b'{') => self.nested(Self::object)?,
+            Some(b'[') => self.nested(Self::array)?,
+            Some(b'"') => Json::String(self.string()?),
+            Some(b'-' | b'0'..=b'9') => self.number()?,
+            Some(_) => self.literal()?,
+        };
+
+        Ok(Value { json, line })
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> JsonResult<Json>) -> JsonResult<Json> {
+        self.depth += 1;
+        let json = parse(self);
+        self.depth -= 1;
+        json
+    }
+
+    fn object(&mut self) -> JsonResult<Json> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.bump();
+            return Ok(Json::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            if fields.iter().any(|(k, _)| *k == key) {
+                return Err(self.error(format!("duplicate field `{key}`")));
+            }
+
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+
+            self.skip_whitespace();
+            match self.bump() {
+                Some(b',') => continue,
+                Some(b'}') => return Ok(Json::Object(fields)),
+                _ => return Err(self.error("expected `,` or `}` in object")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> JsonResult<Json> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.bump();
+            return Ok(Json::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+
+            self.skip_whitespace();
+            match self.bump() {
+                Some(b',') => continue,
+                Some(b']') => return Ok(Json::Array(values)),
+                _ => return Err(self.error("expected `,` or `]` in array")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> JsonResult<String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+
+        loop {
+            match self.bump() {
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escaped = match self.bump() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let hex = self
+                                .bytes
+                                .get(self.pos..self.pos + 4)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or_else(|| self.error("invalid \\u escape"))?;
+                            self.pos += 4;
+                            char::from_u32(hex)
+                                .ok_or_else(|| self.error("unsupported \\u escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    out.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(byte) => out.push(byte),
+            }
+        }
+
+        // the input was a &str and we only split it at ASCII characters
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn number(&mut self) -> JsonResult<Json> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.bump();
+        }
+
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| self.error(format!("invalid number `{text}`")))
+    }
+
+    fn literal(&mut self) -> JsonResult<Json> {
+        for (text, json) in [
+            ("true", Json::Bool(true)),
+            ("false", Json::Bool(false)),
+            ("null", Json::Null),
+        ] {
+            if self.bytes[self.pos..].starts_with(text.as_bytes()) {
+                self.pos += text.len();
+                return Ok(json);
+            }
+        }
+
+        Err(self.error("expected a value"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> JsonError {
+        parse(source).expect_err("expected a parse error")
+    }
+
+    #[test]
+    fn string_escapes() {
+        let value = parse(r#""a\"b\\c\/d\b\f\n\r\t\u00e9\u0041""#).unwrap();
+        assert_eq!(value.as_str().unwrap(), "a\"b\\c/d\u{8}\u{c}\n\r\téA");
+
+        assert_eq!(parse("\"ünïcödé\"").unwrap().as_str().unwrap(), "ünïcödé");
+        assert!(error(r#""\q""#).message.contains("invalid escape"));
+        assert!(error(r#""\u12""#).message.contains("\\u escape"));
+        assert!(error(r#""\ud800""#).message.contains("\\u escape"));
+        assert!(error("\"open").message.contains("unterminated"));
+    }
+
+    #[test]
+    fn numbers() {
+        for (text, expected) in [
+            ("0", 0.),
+            ("-12", -12.),
+            ("3.25", 3.25),
+            ("1e3", 1000.),
+            ("-2.5E-2", -0.025),
+            ("6.02e+23", 6.02e23),
+        ] {
+            assert_eq!(parse(text).unwrap().as_f64().unwrap(), expected, "{text}");
+        }
+
+        for text in ["-", "1.2.3", "1e", "--1"] {
+            assert!(error(text).message.contains("invalid number"), "{text}");
+        }
+    }
+
+    #[test]
+    fn integers_must_be_whole_and_non_negative() {
+        assert_eq!(parse("42").unwrap().as_usize().unwrap(), 42);
+        assert!(parse("-1").unwrap().as_usize().is_err());
+        assert!(parse("1.5").unwrap().as_usize().is_err());
+    }
+
+    #[test]
+    fn values_remember_their_line() {
+        let value = parse("{\n  \"a\": 1,\n\n  \"b\": [\n    true,\n    null\n  ]\n}").unwrap();
+        assert_eq!(value.line, 1);
+        assert_eq!(value.field("a").unwrap().line, 2);
+        let b = value.field("b").unwrap();
+        assert_eq!(b.line, 4);
+        let lines: Vec<_> = b.as_array().unwrap().iter().map(|v| v.line).collect();
+        assert_eq!(lines, [5, 6]);
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        assert_eq!(error("{\n  \"a\": 1,\n  \"a\": 2\n}").line, 3);
+        assert_eq!(error("[\n  1,\n  2\n  3\n]").line, 4);
+        assert_eq!(error("{\n  \"a\": tru\n}").line, 2);
+        assert_eq!(error("[\n  1,\n").line, 3);
+
+        let value = parse("{\n  \"a\": \"x\"\n}").unwrap();
+        let err = value.field("a").unwrap().as_f32().unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.to_string(), "line 2: expected a number, found a string");
+    }
+
+    #[test]
+    fn trailing_characters_are_rejected() {
+        assert!(parse("  {} \n\t ").is_ok());
+
+        let err = error("{}\n\n}");
+        assert_eq!(err.line, 3);
+        assert!(err.message.contains("trailing characters"));
+        assert!(error("1 2").message.contains("trailing characters"));
+        assert!(error("[1],").message.contains("trailing characters"));
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(error(&nested(MAX_DEPTH + 1)).message.contains("nested"));
+
+        // deep enough to overflow the stack without the limit
+        assert!(error(&"{\"a\":".repeat(1_000_000))
+            .message
+            .contains("nested"));
+        assert!(error(&"[".repeat(1_000_000)).message.contains("nested"));
+    }
+}
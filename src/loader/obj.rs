@@ -0,0 +1,769 @@
+use std::{
+    alloc::Allocator,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::SplitWhitespace,
+};
+
+use crate::{
+    hittable::{
+        bvh::Bvh,
+        mesh::{MeshData, MeshFace, TriangleMesh},
+        Hittable,
+    },
+    material::{Dielectric, DiffuseLight, Lambertian, Material},
+    texture::{ImageTexture, SolidColor, Texture},
+    units::{Color, Point, TexCoord, Vector},
+};
+
+use super::LoadError;
+
+// for glass that doesn't give its `Ni`
+const DEFAULT_IOR: f32 = 1.5;
+
+// A parsed Wavefront OBJ file together with the materials of every `mtllib` it references.
+// Faces are split into groups by `g`/`o` statements and by `usemtl`, so each group has a single
+// material.
+pub struct ObjFile {
+    pub path: PathBuf,
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vector>,
+    pub uvs: Vec<TexCoord>,
+    pub groups: Vec<ObjGroup>,
+    pub materials: HashMap<String, MtlMaterial>,
+}
+
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Option<String>,
+    pub faces: Vec<[ObjVertex; 3]>,
+    // where the group's `usemtl` appeared, for error reporting
+    line: usize,
+}
+
+// zero-based indices into the buffers of the `ObjFile`
+#[derive(Clone, Copy, Debug)]
+pub struct ObjVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: Color,
+    pub diffuse_map: Option<PathBuf>,
+    pub emission: Color,
+    // `Ni`, if given
+    pub ior: Option<f32>,
+    pub dissolve: f32,
+    pub illum: u32,
+
+    path: PathBuf,
+    line: usize,
+}
+
+pub fn load<'a, P: AsRef<Path>, A: Allocator + Copy + 'a>(
+    path: P,
+    alloc: A,
+) -> Result<&'a dyn Hittable, LoadError> {
+    ObjFile::open(path)?.build(alloc)
+}
+
+impl ObjFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|err| LoadError::io(path, err))?;
+
+        Self::parse(path, &source)
+    }
+
+    // `path` is used for error messages and to resolve `mtllib` statements
+    pub fn parse<P: AsRef<Path>>(path: P, source: &str) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let mut obj = ObjFile {
+            path: path.into(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            groups: Vec::new(),
+            materials: HashMap::new(),
+        };
+
+        let mut group_name = String::from("default");
+        let mut material: Option<String> = None;
+        let mut current: Option<usize> = None;
+        let mut group_ids: HashMap<(String, Option<String>), usize> = HashMap::new();
+
+        for (line_no, line) in source.lines().enumerate() {
+            let line_no = line_no + 1;
+            let err = |message: String| LoadError::at_line(path, line_no, message);
+
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            match keyword {
+                "v" => {
+                    // followed by an optional w, or by a vertex color, neither of which is used
+                    let [x, y, z] = parse_floats(&mut tokens, 3, 7).map_err(err)?;
+                    obj.positions.push(Point::new(x, y, z));
+                }
+                "vn" => {
+                    let [x, y, z] = parse_floats(&mut tokens, 3, 3).map_err(err)?;
+                    obj.normals.push(Vector::new(x, y, z));
+                }
+                "vt" => {
+                    let [u, v, _] = parse_floats(&mut tokens, 1, 3).map_err(err)?;
+                    obj.uvs.push(TexCoord::new(u, v));
+                }
+                "f" => {
+                    let vertices = tokens
+                        .map(|token| obj.parse_vertex(token))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(err)?;
+                    if vertices.len() < 3 {
+                        return Err(err(format!(
+                            "face has {} vertices, expected at least 3",
+                            vertices.len()
+                        )));
+                    }
+
+                    let group = *current.get_or_insert_with(|| {
+                        *group_ids
+                            .entry((group_name.clone(), material.clone()))
+                            .or_insert_with(|| {
+                                obj.groups.push(ObjGroup {
+                                    name: group_name.clone(),
+                                    material: material.clone(),
+                                    faces: Vec::new(),
+                                    line: line_no,
+                                });
+                                obj.groups.len() - 1
+                            })
+                    });
+
+                    // triangulate as a fan around the first vertex
+                    let faces = &mut obj.groups[group].faces;
+                    for pair in vertices[1..].windows(2) {
+                        faces.push([vertices[0], pair[0], pair[1]]);
+                    }
+                }
+                "g" | "o" => {
+                    let name = tokens.collect::<Vec<_>>().join(" ");
+                    group_name = if name.is_empty() {
+                        String::from("default")
+                    } else {
+                        name
+                    };
+                    current = None;
+                }
+                "usemtl" => {
+                    let name = tokens.collect::<Vec<_>>().join(" ");
+                    if name.is_empty() {
+                        return Err(err(String::from("usemtl without a material name")));
+                    }
+                    material = Some(name);
+                    current = None;
+                }
+                "mtllib" => {
+                    let dir = path.parent().unwrap_or(Path::new(""));
+                    for lib in tokens {
+                        let lib_path = dir.join(lib);
+                        let lib_source = fs::read_to_string(&lib_path).map_err(|io| {
+                            err(format!(
+                                "cannot read material library {}: {}",
+                                lib_path.display(),
+                                io
+                            ))
+                        })?;
+                        for mtl in MtlMaterial::parse(&lib_path, &lib_source)? {
+                            obj.materials.insert(mtl.name.clone(), mtl);
+                        }
+                    }
+                }
+                // smoothing groups, lines, points etc. don't affect what we render
+                _ => {}
+            }
+        }
+
+        Ok(obj)
+    }
+
+    // parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face vertex; negative indices are relative to
+    // the end of the buffer as it is so far
+    fn parse_vertex(&self, token: &str) -> Result<ObjVertex, String> {
+        let mut parts = token.split('/');
+
+        let position = parts.next().unwrap_or_default();
+        let position = resolve_index(position, self.positions.len(), "position")?;
+
+        let uv = match parts.next() {
+            None | Some("") => None,
+            Some(uv) => Some(resolve_index(uv, self.uvs.len(), "texture coordinate")?),
+        };
+        let normal = match parts.next() {
+            None | Some("") => None,
+            Some(normal) => Some(resolve_index(normal, self.normals.len(), "normal")?),
+        };
+
+        if parts.next().is_some() {
+            return Err(format!("malformed face vertex {token:?}"));
+        }
+
+        Ok(ObjVertex {
+            position,
+            uv,
+            normal,
+        })
+    }
+
+    pub fn group_mesh(&self, group: &ObjGroup) -> MeshData {
+        fn remap<T: Copy>(
+            map: &mut HashMap<usize, u32>,
+            local: &mut Vec<T>,
+            global: &[T],
+            idx: usize,
+        ) -> u32 {
+            *map.entry(idx).or_insert_with(|| {
+                local.push(global[idx]);
+                (local.len() - 1) as u32
+            })
+        }
+
+        let mut data = MeshData::default();
+        let (mut positions, mut normals, mut uvs) =
+            (HashMap::new(), HashMap::new(), HashMap::new());
+
+        for face in &group.faces {
+            let face_positions = face.map(|v| {
+                remap(
+                    &mut positions,
+                    &mut data.positions,
+                    &self.positions,
+                    v.position,
+                )
+            });
+
+            let face_normals = face.iter().all(|v| v.normal.is_some()).then(|| {
+                face.map(|v| {
+                    remap(
+                        &mut normals,
+                        &mut data.normals,
+                        &self.normals,
+                        v.normal.unwrap(),
+                    )
+                })
+            });
+
+            let face_uvs = face
+                .iter()
+                .all(|v| v.uv.is_some())
+                .then(|| face.map(|v| remap(&mut uvs, &mut data.uvs, &self.uvs, v.uv.unwrap())));
+
+            data.faces.push(MeshFace {
+                positions: face_positions,
+                normals: face_normals,
+                uvs: face_uvs,
+            });
+        }
+
+        data
+    }
+
+    pub fn build<'a, A: Allocator + Copy + 'a>(
+        &self,
+        alloc: A,
+    ) -> Result<&'a dyn Hittable, LoadError> {
+        let mut materials: HashMap<&str, &'a dyn Material> = HashMap::new();
+        let default_material: &'a dyn Material = Box::leak(Box::new_in(
+            Lambertian::new_with_color(Color::splat(0.8), alloc),
+            alloc,
+        ));
+
+        let mut meshes: Vec<&'a dyn Hittable> = Vec::with_capacity(self.groups.len());
+        for group in &self.groups {
+            let material = match &group.material {
+                None => default_material,
+                Some(name) => match materials.get(name.as_str()) {
+                    Some(&material) => material,
+                    None => {
+                        let mtl = self.materials.get(name).ok_or_else(|| {
+                            LoadError::at_line(
+                                &self.path,
+                                group.line,
+                                format!("unknown material {name:?}"),
+                            )
+                        })?;
+                        let material = mtl.build(alloc)?;
+                        materials.insert(name.as_str(), material);
+                        material
+                    }
+                },
+            };
+
+            let mesh = TriangleMesh::new(self.group_mesh(group), material, alloc);
+            meshes.push(Box::leak(Box::new_in(mesh, alloc)));
+        }
+
+        Ok(Box::leak(Box::new_in(Bvh::new(meshes), alloc)))
+    }
+}
+
+impl MtlMaterial {
+    fn new(name: String, path: &Path, line: usize) -> Self {
+        Self {
+            name,
+            diffuse: Color::splat(0.8),
+            diffuse_map: None,
+            emission: Color::ZERO,
+            ior: None,
+            dissolve: 1.0,
+            illum: 2,
+            path: path.into(),
+            line,
+        }
+    }
+
+    pub fn parse<P: AsRef<Path>>(path: P, source: &str) -> Result<Vec<Self>, LoadError> {
+        let path = path.as_ref();
+        let mut materials = Vec::new();
+
+        for (line_no, line) in source.lines().enumerate() {
+            let line_no = line_no + 1;
+            let err = |message: String| LoadError::at_line(path, line_no, message);
+
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            if keyword == "newmtl" {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if name.is_empty() {
+                    return Err(err(String::from("newmtl without a material name")));
+                }
+                materials.push(Self::new(name, path, line_no));
+                continue;
+            }
+
+            let Some(mtl) = materials.last_mut() else {
+                return Err(err(format!("{keyword} before any newmtl")));
+            };
+
+            match keyword {
+                // spectral curves aren't supported, which leaves the defaults
+                "Kd" => {
+                    if let Some(color) = parse_color(&mut tokens).map_err(err)? {
+                        mtl.diffuse = color;
+                    }
+                }
+                "Ke" => {
+                    if let Some(color) = parse_color(&mut tokens).map_err(err)? {
+                        mtl.emission = color;
+                    }
+                }
+                "Ni" => {
+                    let [ior, _, _] = parse_floats(&mut tokens, 1, 1).map_err(err)?;
+                    mtl.ior = Some(ior);
+                }
+                "d" => {
+                    // `-halo` fades the dissolve towards the silhouette, which is left out
+                    if tokens.clone().next() == Some("-halo") {
+                        tokens.next();
+                    }
+                    [mtl.dissolve, _, _] = parse_floats(&mut tokens, 1, 1).map_err(err)?;
+                }
+                "Tr" => {
+                    let [transparency, _, _] = parse_floats(&mut tokens, 1, 1).map_err(err)?;
+                    mtl.dissolve = 1.0 - transparency;
+                }
+                "illum" => {
+                    let illum = tokens.next().unwrap_or_default();
+                    mtl.illum = illum
+                        .parse()
+                        .map_err(|_| err(format!("invalid illumination model {illum:?}")))?;
+                }
+                "map_Kd" => {
+                    // options such as `-s` come before the file name, which we take to be last
+                    let file = tokens
+                        .last()
+                        .ok_or_else(|| err(String::from("map_Kd without a file name")))?;
+                    let dir = path.parent().unwrap_or(Path::new(""));
+                    mtl.diffuse_map = Some(dir.join(file));
+                    mtl.line = line_no;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(materials)
+    }
+
+    // emissive materials become lights, refractive ones glass, and everything else a diffuse
+    // surface. Exporters write `d` below 1 for all kinds of partly see-through materials, so that
+    // only makes glass together with an index of refraction.
+    pub fn build<'a, A: Allocator + Copy + 'a>(
+        &self,
+        alloc: A,
+    ) -> Result<&'a dyn Material, LoadError> {
+        if self.emission.max_element() > 0.0 {
+            let light = DiffuseLight::new_with_color(self.emission, alloc);
+            return Ok(Box::leak(Box::new_in(light, alloc)));
+        }
+
+        if matches!(self.illum, 4 | 6 | 7) || (self.dissolve < 1.0 && self.ior.is_some()) {
+            let ir = self.ior.unwrap_or(DEFAULT_IOR);
+            return Ok(Box::leak(Box::new_in(Dielectric { ir }, alloc)));
+        }
+
+        let albedo: &'a dyn Texture = match &self.diffuse_map {
+            Some(map) => {
+                let texture = ImageTexture::from_path(map).ok_or_else(|| {
+                    LoadError::at_line(
+                        &self.path,
+                        self.line,
+                        format!("cannot load texture {}", map.display()),
+                    )
+                })?;
+                Box::leak(Box::new_in(texture, alloc))
+            }
+            None => Box::leak(Box::new_in(
+                SolidColor {
+                    color: self.diffuse,
+                },
+                alloc,
+            )),
+        };
+
+        Ok(Box::leak(Box::new_in(Lambertian { albedo }, alloc)))
+    }
+}
+
+fn resolve_index(token: &str, len: usize, what: &str) -> Result<usize, String> {
+    let idx: isize = token
+        .parse()
+        .map_err(|_| format!("invalid {what} index {token:?}"))?;
+
+    let resolved = match idx {
+        0 => None,
+        1.. => Some(idx as usize - 1),
+        _ => len.checked_sub(idx.unsigned_abs()),
+    };
+
+    resolved
+        .filter(|&i| i < len)
+        .ok_or_else(|| format!("{what} index {idx} is out of range ({len} defined so far)"))
+}
+
+// parses between `min` and `max` (at most 3) floats, filling the rest with 0
+fn parse_floats(tokens: &mut SplitWhitespace, min: usize, max: usize) -> Result<[f32; 3], String> {
+    let mut out = [0.0; 3];
+    let mut count = 0;
+
+    for token in tokens {
+        if count == max {
+            return Err(format!("expected at most {max} numbers"));
+        }
+        let value = token
+            .parse::<f32>()
+            .map_err(|_| format!("invalid number {token:?}"))?;
+        if count < 3 {
+            out[count] = value;
+        }
+        count += 1;
+    }
+
+    if count < min {
+        return Err(format!("expected at least {min} numbers, found {count}"));
+    }
+
+    Ok(out)
+}
+
+// an `r g b` color, or `xyz x y z` in CIE XYZ; `spectral` curves give `None`
+fn parse_color(tokens: &mut SplitWhitespace) -> Result<Option<Color>, String> {
+    let mut peek = tokens.clone();
+    match peek.next() {
+        Some("spectral") => Ok(None),
+        Some("xyz") => {
+            let [x, y, z] = parse_floats(&mut peek, 3, 3)?;
+            // to linear sRGB, with its D65 white point
+            Ok(Some(Color::new(
+                3.2406 * x - 1.5372 * y - 0.4986 * z,
+                -0.9689 * x + 1.8758 * y + 0.0415 * z,
+                0.0557 * x - 0.2040 * y + 1.057 * z,
+            )))
+        }
+        _ => {
+            let [r, g, b] = parse_floats(tokens, 3, 3)?;
+            Ok(Some(Color::new(r, g, b)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::Global;
+
+    use image::{Rgb, RgbImage};
+
+    use crate::{
+        hittable::{triangle::Triangle, Interval},
+        ray::Ray,
+    };
+
+    use super::*;
+
+    fn positions(group: &ObjGroup) -> Vec<[usize; 3]> {
+        group
+            .faces
+            .iter()
+            .map(|face| face.map(|v| v.position))
+            .collect()
+    }
+
+    fn parse_error(source: &str) -> String {
+        match ObjFile::parse("models/test.obj", source) {
+            Ok(_) => panic!("expected an error parsing\n{source}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    // the albedo and emission of `material` where a ray hits it head-on
+    fn shade(material: &dyn Material) -> (Color, Color) {
+        let triangle = Triangle::new(
+            Point::new(-1., -1., 0.),
+            Point::new(1., -1., 0.),
+            Point::new(0., 1., 0.),
+            material,
+        );
+        let ray = Ray {
+            origin: Point::new(0., 0., 1.),
+            direct: Vector::new(0., 0., -1.),
+            time: 0.,
+        };
+        let hit = triangle.hit(&ray, Interval::<f32>::POSITIVE).unwrap();
+        (hit.mat.albedo(&hit), hit.mat.emitted(&ray, &hit))
+    }
+
+    fn mtl(source: &str) -> MtlMaterial {
+        let mut materials = MtlMaterial::parse("models/test.mtl", source).unwrap();
+        assert_eq!(materials.len(), 1);
+        materials.pop().unwrap()
+    }
+
+    #[test]
+    fn polygons_become_triangle_fans() {
+        let obj = ObjFile::parse(
+            "models/test.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 1 0\nf 1 2 3 4 5\nf 1 2 3\n",
+        )
+        .unwrap();
+
+        assert_eq!(obj.groups.len(), 1);
+        assert_eq!(
+            positions(&obj.groups[0]),
+            [[0, 1, 2], [0, 2, 3], [0, 3, 4], [0, 1, 2]]
+        );
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_vertex() {
+        let obj = ObjFile::parse(
+            "models/test.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nvn 0 0 1\nf -3 -2 -1\nv 0 1 0\nf -4//-1 -2//1 -1//-1\n",
+        )
+        .unwrap();
+
+        let group = &obj.groups[0];
+        assert_eq!(positions(group), [[0, 1, 2], [0, 2, 3]]);
+        for v in group.faces[1] {
+            assert_eq!((v.uv, v.normal), (None, Some(0)));
+        }
+    }
+
+    #[test]
+    fn face_vertices_with_uvs_and_normals() {
+        let obj = ObjFile::parse(
+            "models/test.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 1\n\
+             f 1/1 2/2 3/3\nf 1//1 2//1 3//1\nf 1/3/1 2/2/1 3/1/1\n",
+        )
+        .unwrap();
+
+        let faces = &obj.groups[0].faces;
+        let attributes = |face: &[ObjVertex; 3]| face.map(|v| (v.uv, v.normal));
+        assert_eq!(
+            attributes(&faces[0]),
+            [(Some(0), None), (Some(1), None), (Some(2), None)]
+        );
+        assert_eq!(attributes(&faces[1]), [(None, Some(0)); 3]);
+        assert_eq!(
+            attributes(&faces[2]),
+            [(Some(2), Some(0)), (Some(1), Some(0)), (Some(0), Some(0))]
+        );
+
+        let mesh = obj.group_mesh(&obj.groups[0]);
+        assert_eq!(mesh.faces[0].normals, None);
+        assert!(mesh.faces[1].uvs.is_none());
+        assert_eq!(mesh.faces[2].normals, Some([0; 3]));
+    }
+
+    #[test]
+    fn bad_indices_point_at_their_line() {
+        let source = "v 0 0 0\nv 1 0 0\n\nv 1 1 0\nf 1 2 4\n";
+        assert_eq!(
+            parse_error(source),
+            "models/test.obj:5: position index 4 is out of range (3 defined so far)"
+        );
+
+        // a vertex defined later doesn't count yet
+        let source = "v 0 0 0\nv 1 0 0\nf 1 2 -3\nv 1 1 0\n";
+        assert_eq!(
+            parse_error(source),
+            "models/test.obj:3: position index -3 is out of range (2 defined so far)"
+        );
+
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1/1 2/1 3/1\n";
+        assert_eq!(
+            parse_error(source),
+            "models/test.obj:4: texture coordinate index 1 is out of range (0 defined so far)"
+        );
+        assert_eq!(
+            parse_error("v 0 0 0\nf 0 1 1\n"),
+            "models/test.obj:2: position index 0 is out of range (1 defined so far)"
+        );
+        assert_eq!(
+            parse_error("v 0 0 0\nv 1 0 0\nf 1 2\n"),
+            "models/test.obj:3: face has 2 vertices, expected at least 3"
+        );
+    }
+
+    #[test]
+    fn faces_are_grouped_by_name_and_material() {
+        let obj = ObjFile::parse(
+            "models/test.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\n\
+             f 1 2 3\n\
+             g wall\nusemtl red\nf 1 2 3\nusemtl blue\nf 2 3 1\n\
+             o floor\nf 3 1 2\n\
+             g wall\nusemtl red\nf 3 2 1\n",
+        )
+        .unwrap();
+
+        let groups: Vec<_> = obj
+            .groups
+            .iter()
+            .map(|group| {
+                (
+                    group.name.as_str(),
+                    group.material.as_deref(),
+                    group.faces.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("default", None, 1),
+                ("wall", Some("red"), 2),
+                ("wall", Some("blue"), 1),
+                ("floor", Some("blue"), 1),
+            ]
+        );
+        assert_eq!(positions(&obj.groups[1]), [[0, 1, 2], [2, 1, 0]]);
+    }
+
+    #[test]
+    fn unknown_materials_point_at_their_faces() {
+        let obj = ObjFile::parse(
+            "models/test.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nusemtl missing\nf 1 2 3\n",
+        )
+        .unwrap();
+        match obj.build(Global) {
+            Ok(_) => panic!("expected an unknown material"),
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "models/test.obj:5: unknown material \"missing\""
+            ),
+        }
+    }
+
+    #[test]
+    fn emissive_materials_become_lights() {
+        let light = mtl("newmtl lamp\nKd 0.5 0.5 0.5\nKe 4 3 2\nillum 4\n");
+        let (albedo, emission) = shade(light.build(Global).unwrap());
+        assert_eq!(albedo, Color::ZERO);
+        assert_eq!(emission, Color::new(4., 3., 2.));
+    }
+
+    #[test]
+    fn refractive_materials_become_glass() {
+        for source in [
+            "newmtl glass\nillum 4\n",
+            "newmtl glass\nd 0.2\nNi 1.33\n",
+            "newmtl glass\nTr 0.8\nNi 1.5\nillum 2\n",
+        ] {
+            let (albedo, emission) = shade(mtl(source).build(Global).unwrap());
+            assert_eq!((albedo, emission), (Color::ONE, Color::ZERO), "{source}");
+        }
+
+        let glass = mtl("newmtl glass\nillum 7\n");
+        assert_eq!(glass.ior, None);
+        assert_eq!(mtl("newmtl glass\nNi 1.33\n").ior, Some(1.33));
+
+        // see-through without an index of refraction stays diffuse
+        let (albedo, _) = shade(
+            mtl("newmtl leaf\nKd 0.1 0.6 0.2\nd 0.5\n")
+                .build(Global)
+                .unwrap(),
+        );
+        assert_eq!(albedo, Color::new(0.1, 0.6, 0.2));
+    }
+
+    #[test]
+    fn diffuse_maps_become_textures() {
+        let dir = std::env::temp_dir().join(format!("raytracing-mtl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        RgbImage::from_pixel(2, 2, Rgb([255, 0, 255]))
+            .save(dir.join("magenta.png"))
+            .unwrap();
+
+        let source = "newmtl painted\nKd 0.2 0.2 0.2\nmap_Kd -s 1 1 1 magenta.png\n";
+        let mut materials = MtlMaterial::parse(dir.join("test.mtl"), source).unwrap();
+        let painted = materials.pop().unwrap();
+        assert_eq!(painted.diffuse_map, Some(dir.join("magenta.png")));
+        let (albedo, _) = shade(painted.build(Global).unwrap());
+        assert_eq!(albedo, Color::new(1., 0., 1.));
+
+        let source = "newmtl painted\n\nmap_Kd missing.png\n";
+        let mut materials = MtlMaterial::parse(dir.join("test.mtl"), source).unwrap();
+        match materials.pop().unwrap().build(Global) {
+            Ok(_) => panic!("expected a missing texture"),
+            Err(err) => assert_eq!(err.line, Some(3)),
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn plain_materials_stay_diffuse() {
+        let (albedo, emission) = shade(mtl("newmtl red\nKd 0.8 0.1 0.1\n").build(Global).unwrap());
+        assert_eq!((albedo, emission), (Color::new(0.8, 0.1, 0.1), Color::ZERO));
+
+        // spectral curves keep the default
+        let (albedo, _) = shade(
+            mtl("newmtl odd\nKd spectral red.spd\n")
+                .build(Global)
+                .unwrap(),
+        );
+        assert_eq!(albedo, Color::splat(0.8));
+    }
+}
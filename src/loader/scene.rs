@@ -0,0 +1,1012 @@
+// Scene files are JSON documents of the form
+//
+// {
+//     "camera": { "image_width": 600, "lookfrom": [278, 278, -800], ... },
+//     "textures": { "name": { "type": "checker", "scale": 0.32, "even": [0.2, 0.3, 0.1], ... } },
+//     "materials": { "name": { "type": "lambertian", "albedo": "some texture" }, ... },
+//     "objects": [ { "type": "sphere", "center": [0, 1, 0], "radius": 1, "material": "name" } ]
+// }
+//
+// Wherever a texture or material is expected, it can be given by name, inline as an object, or
+// (for textures) as an `[r, g, b]` color. See `scenes/` for complete examples.
+//
+// The top-level objects are put in a BVH unless `"bvh": false` is given; `"bvh": "sah"` builds it
+// with the surface area heuristic instead of median splits. The same choice is available for
+// nested groups through `{ "type": "bvh", "strategy": "sah", "objects": [...] }`.
+//
+// Moving objects and cameras are animated over a [0, 1] time range, which `"frames": n` in the
+// camera splits into as many frames. The camera can be given a `"shutter": [open, close]` interval
+// within each frame, and a `"shutter_curve"`. Its `lookfrom` and `lookat` can follow keyframes too.
+//
+// The camera's `"sampler"` picks where the samples of each pixel go: `"independent"`,
+// `"stratified"`, `"halton"`, `"sobol"` (the default) or `"blue_noise"`, and its `"filter"` how
+// samples are shared between neighbouring pixels.
+//
+// Spheres and quads with a `diffuse_light` material are also collected into `Scene::lights`, so
+// that the renderer can sample them directly.
+//
+// The camera's `"aovs"` lists buffers to render alongside the image, like `["depth", "normal"]`.
+// For the ID ones, the top-level objects are numbered from 1 in the order they are listed (a group
+// gets a single ID), and the materials from 1 in the order they are defined, inline ones included.
+
+use std::{
+    alloc::Allocator,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rand::SeedableRng;
+
+use crate::{
+    aov::Aov,
+    camera::{AdaptiveSampling, CameraBuilder, ShutterCurve},
+    filter::Filter,
+    hittable::{
+        bvh::{Bvh, BvhStrategy},
+        instances::{Keyframe, MotionTransform, Rotate, Transform, Translate},
+        quad::Quad,
+        triangle::Triangle,
+        Hittable, HittableList, Identified, Sphere,
+    },
+    material::{self, Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    sampler,
+    texture::{perlin::NoiseTexture, GlobalChecker, ImageTexture, SolidColor, Texture},
+    tiles::TileOrder,
+    time_utils::{Easing, Keyframes, Linear, PiecewiseLinear, Spline, SplinePath, Unchanging},
+    units::{Color, Point, RenderRng, Vector},
+};
+
+use super::{
+    json::{self, JsonError, Value},
+    obj, LoadError,
+};
+
+pub struct Scene<'a> {
+    pub camera: CameraBuilder,
+    pub world: &'a dyn Hittable,
+    pub lights: Vec<&'a dyn Hittable>,
+}
+
+pub fn load<'a, P: AsRef<Path>, A: Allocator + Copy + 'a>(
+    path: P,
+    alloc: A,
+) -> Result<Scene<'a>, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|err| LoadError::io(path, err))?;
+
+    parse(path, &source, alloc)
+}
+
+// `path` is used for error messages and to resolve relative paths to images and meshes
+pub fn parse<'a, P: AsRef<Path>, A: Allocator + Copy + 'a>(
+    path: P,
+    source: &str,
+    alloc: A,
+) -> Result<Scene<'a>, LoadError> {
+    let path = path.as_ref();
+    let to_load_error = |err: Error| match err {
+        Error::Json(err) => LoadError::at_line(path, err.line, err.message),
+        Error::Load(err) => err,
+    };
+
+    let root = json::parse(source).map_err(|err| to_load_error(err.into()))?;
+    SceneBuilder::new(path, alloc)
+        .scene(&root)
+        .map_err(to_load_error)
+}
+
+enum Error {
+    Json(JsonError),
+    Load(LoadError),
+}
+
+impl From<JsonError> for Error {
+    fn from(err: JsonError) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(err: LoadError) -> Self {
+        Self::Load(err)
+    }
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+struct SceneBuilder<'a, A> {
+    dir: PathBuf,
+    alloc: A,
+    textures: HashMap<String, &'a dyn Texture>,
+    materials: HashMap<String, &'a dyn Material>,
+    // addresses of the `diffuse_light` materials, to recognise the objects using them
+    emissive: HashSet<*const ()>,
+    // how many materials have been defined, to number them
+    material_count: u32,
+    lights: Vec<&'a dyn Hittable>,
+    rng: RenderRng,
+}
+
+impl<'a, A: Allocator + Copy + 'a> SceneBuilder<'a, A> {
+    fn new(path: &Path, alloc: A) -> Self {
+        Self {
+            dir: path.parent().unwrap_or(Path::new("")).into(),
+            alloc,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            emissive: HashSet::new(),
+            material_count: 0,
+            lights: Vec::new(),
+            rng: RenderRng::seed_from_u64(0),
+        }
+    }
+
+    fn alloc<T: 'a>(&self, x: T) -> &'a T {
+        Box::leak(Box::new_in(x, self.alloc))
+    }
+
+    fn scene(mut self, root: &Value) -> Result<Scene<'a>> {
+        root.check_fields(&["camera", "textures", "materials", "objects", "bvh"])?;
+
+        let mut camera = CameraBuilder::default();
+        if let Some(value) = root.get("camera") {
+            self.camera(value, &mut camera)?;
+        }
+
+        if let Some(textures) = root.get("textures") {
+            for (name, value) in textures.as_object()? {
+                let texture = self.texture(value)?;
+                self.textures.insert(name.clone(), texture);
+            }
+        }
+
+        if let Some(materials) = root.get("materials") {
+            for (name, value) in materials.as_object()? {
+                let material = self.material(value)?;
+                self.materials.insert(name.clone(), material);
+            }
+        }
+
+        let objects = root.field("objects")?;
+        let strategy = match root.get("bvh") {
+            Some(value) => match value.as_bool() {
+                Ok(use_bvh) => use_bvh.then_some(BvhStrategy::Median),
+                Err(_) => Some(bvh_strategy(value)?),
+            },
+            None => Some(BvhStrategy::Median),
+        };
+        // only the top-level objects get IDs, so that a group is one object for compositing
+        let objects = self
+            .objects(objects)?
+            .into_iter()
+            .zip(1..)
+            .map(|(object, id)| self.alloc(Identified { object, id }) as &'a dyn Hittable)
+            .collect();
+        let world = self.group(objects, strategy);
+
+        Ok(Scene {
+            camera,
+            world,
+            lights: self.lights,
+        })
+    }
+
+    fn camera(&mut self, value: &Value, camera: &mut CameraBuilder) -> Result<()> {
+        for (key, value) in value.as_object()? {
+            match key.as_str() {
+                "aspect_ratio" => camera.with_aspect_ratio(value.as_f32()?),
+                "image_width" => camera.with_image_width(value.as_usize()?),
+                "image_height" => camera.with_image_height(value.as_usize()?),
+                "vfov" => camera.with_vfov(value.as_f32()?),
+                "lookfrom" => match camera_point(value)? {
+                    CameraPoint::Fixed(lookfrom) => camera.with_lookfrom(lookfrom),
+                    CameraPoint::Moving(keyframes) => camera.with_lookfrom_keyframes(keyframes),
+                },
+                "lookat" => match camera_point(value)? {
+                    CameraPoint::Fixed(lookat) => camera.with_lookat(lookat),
+                    CameraPoint::Moving(keyframes) => camera.with_lookat_keyframes(keyframes),
+                },
+                "vup" => camera.with_vup(vector(value)?),
+                "defocus_angle" => camera.with_defocus_angle(value.as_f32()?),
+                "focus_dist" => camera.with_focus_dist(value.as_f32()?),
+                "shutter" => {
+                    let [open, close] = value.as_f32_array()?;
+                    if !(0. <= open && open <= close && close <= 1.) {
+                        return Err(JsonError::new(
+                            value.line,
+                            "the shutter has to open and close within [0, 1]",
+                        )
+                        .into());
+                    }
+                    camera.with_shutter(open, close)
+                }
+                "shutter_curve" => camera.with_shutter_curve(shutter_curve(value)?),
+                "filter" => camera.with_filter(filter(value)?),
+                "aovs" => camera.with_aovs(&aovs(value)?),
+                "frames" => {
+                    let frames = value.as_usize()?;
+                    if frames == 0 {
+                        return Err(JsonError::new(value.line, "an animation needs a frame").into());
+                    }
+                    camera.with_frames(frames)
+                }
+                "adaptive" => camera.with_adaptive_sampling(adaptive_sampling(value)?),
+                "sampler" => {
+                    let name = value.as_str()?;
+                    let sampler = sampler::from_name(name).ok_or_else(|| {
+                        JsonError::new(
+                            value.line,
+                            format!(
+                                "unknown sampler `{name}` (expected independent, stratified, \
+                                 halton, sobol or blue_noise)"
+                            ),
+                        )
+                    })?;
+                    camera.with_sampler(sampler)
+                }
+                "tile_size" => {
+                    let tile_size = value.as_usize()?;
+                    if tile_size == 0 {
+                        return Err(JsonError::new(value.line, "tiles can't be empty").into());
+                    }
+                    camera.with_tile_size(tile_size)
+                }
+                "tile_order" => {
+                    let name = value.as_str()?;
+                    let tile_order = TileOrder::from_name(name).ok_or_else(|| {
+                        JsonError::new(
+                            value.line,
+                            format!(
+                                "unknown tile order `{name}` (expected scanline, spiral or hilbert)"
+                            ),
+                        )
+                    })?;
+                    camera.with_tile_order(tile_order)
+                }
+                "samples_per_pixel" => camera.with_samples_per_pixel(value.as_usize()?),
+                "max_depth" => camera.with_max_depth(value.as_usize()?),
+                "background" => camera.with_background(color(value)?),
+                "seed" => {
+                    let seed = value.as_u64()?;
+                    self.rng = RenderRng::seed_from_u64(seed);
+                    camera.with_seed(seed)
+                }
+                _ => {
+                    return Err(JsonError::new(
+                        value.line,
+                        format!("unknown camera setting `{key}`"),
+                    )
+                    .into())
+                }
+            };
+        }
+
+        Ok(())
+    }
+
+    fn texture(&mut self, value: &Value) -> Result<&'a dyn Texture> {
+        if let Ok(name) = value.as_str() {
+            return self.textures.get(name).copied().ok_or_else(|| {
+                JsonError::new(value.line, format!("unknown texture `{name}`")).into()
+            });
+        }
+        if let Ok(color) = color(value) {
+            return Ok(self.alloc(SolidColor { color }));
+        }
+
+        let texture: &'a dyn Texture = match type_of(value)? {
+            "solid" => {
+                value.check_fields(&["type", "color"])?;
+                self.alloc(SolidColor {
+                    color: color(value.field("color")?)?,
+                })
+            }
+            "checker" => {
+                value.check_fields(&["type", "scale", "even", "odd"])?;
+                let scale = value.field("scale")?.as_f32()?;
+                let even = self.texture(value.field("even")?)?;
+                let odd = self.texture(value.field("odd")?)?;
+                self.alloc(GlobalChecker::new(scale, even, odd))
+            }
+            "image" => {
+                value.check_fields(&["type", "path"])?;
+                let path = self.dir.join(value.field("path")?.as_str()?);
+                let image = ImageTexture::from_path(&path).ok_or_else(|| {
+                    JsonError::new(value.line, format!("cannot load image {}", path.display()))
+                })?;
+                self.alloc(image)
+            }
+            "noise" => {
+                value.check_fields(&["type", "scale"])?;
+                let scale = value.field("scale")?.as_f32()?;
+                let noise = NoiseTexture::new(scale, &mut self.rng);
+                self.alloc(noise)
+            }
+            other => return Err(unknown_type(value, "texture", other)),
+        };
+
+        Ok(texture)
+    }
+
+    fn material(&mut self, value: &Value) -> Result<&'a dyn Material> {
+        if let Ok(name) = value.as_str() {
+            return self.materials.get(name).copied().ok_or_else(|| {
+                JsonError::new(value.line, format!("unknown material `{name}`")).into()
+            });
+        }
+
+        let mut emissive = false;
+        let material: &'a dyn Material = match type_of(value)? {
+            "lambertian" => {
+                value.check_fields(&["type", "albedo"])?;
+                let albedo = self.texture(value.field("albedo")?)?;
+                self.alloc(Lambertian { albedo })
+            }
+            "metal" => {
+                value.check_fields(&["type", "albedo", "fuzz"])?;
+                let albedo = color(value.field("albedo")?)?;
+                let fuzz = match value.get("fuzz") {
+                    Some(fuzz) => fuzz.as_f32()?,
+                    None => 0.0,
+                };
+                self.alloc(Metal { albedo, fuzz })
+            }
+            "dielectric" => {
+                value.check_fields(&["type", "ir"])?;
+                let ir = value.field("ir")?.as_f32()?;
+                self.alloc(Dielectric { ir })
+            }
+            "diffuse_light" => {
+                value.check_fields(&["type", "emit"])?;
+                let emit = self.texture(value.field("emit")?)?;
+                emissive = true;
+                self.alloc(DiffuseLight { emit })
+            }
+            other => return Err(unknown_type(value, "material", other)),
+        };
+
+        self.material_count += 1;
+        let material = self.alloc(material::Identified {
+            material,
+            id: self.material_count,
+        });
+        if emissive {
+            self.emissive
+                .insert(material as *const material::Identified as *const ());
+        }
+
+        Ok(material)
+    }
+
+    fn objects(&mut self, objects: &Value) -> Result<Vec<&'a dyn Hittable>> {
+        objects
+            .as_array()?
+            .iter()
+            .map(|object| self.object(object))
+            .collect()
+    }
+
+    fn group(
+        &self,
+        objects: Vec<&'a dyn Hittable>,
+        strategy: Option<BvhStrategy>,
+    ) -> &'a dyn Hittable {
+        match strategy {
+            Some(strategy) => self.alloc(Bvh::new_with_strategy(objects, strategy)),
+            None => self.alloc(HittableList::from_vec(objects)),
+        }
+    }
+
+    fn is_emissive(&self, material: &dyn Material) -> bool {
+        self.emissive
+            .contains(&(material as *const dyn Material as *const ()))
+    }
+
+    fn object(&mut self, value: &Value) -> Result<&'a dyn Hittable> {
+        let object: &'a dyn Hittable = match type_of(value)? {
+            "sphere" => {
+                value.check_fields(&[
+                    "type",
+                    "center",
+                    "center_end",
+                    "path",
+                    "radius",
+                    "material",
+                ])?;
+                let radius = value.field("radius")?.as_f32()?;
+                let material = self.material(value.field("material")?)?;
+                let sphere: &'a dyn Hittable = match (value.get("path"), value.get("center_end")) {
+                    (Some(path), _) => self.sphere_on_path(path, radius, material)?,
+                    (None, Some(end)) => {
+                        let (center, end) = (point(value.field("center")?)?, point(end)?);
+                        self.alloc(Sphere::<Linear>::new((center, end), radius, material))
+                    }
+                    (None, None) => {
+                        let center = point(value.field("center")?)?;
+                        self.alloc(Sphere::<Unchanging>::new(center, radius, material))
+                    }
+                };
+                if self.is_emissive(material) {
+                    self.lights.push(sphere);
+                }
+                sphere
+            }
+            "quad" => {
+                value.check_fields(&["type", "q", "u", "v", "material"])?;
+                let material = self.material(value.field("material")?)?;
+                let quad = self.alloc(Quad::new(
+                    point(value.field("q")?)?,
+                    vector(value.field("u")?)?,
+                    vector(value.field("v")?)?,
+                    material,
+                ));
+                if self.is_emissive(material) {
+                    self.lights.push(quad);
+                }
+                quad
+            }
+            "box" => {
+                value.check_fields(&["type", "min", "max", "material"])?;
+                let faces = Quad::new_box(
+                    point(value.field("min")?)?,
+                    point(value.field("max")?)?,
+                    self.material(value.field("material")?)?,
+                );
+                let faces = faces
+                    .into_iter()
+                    .map(|face| self.alloc(face) as &dyn Hittable)
+                    .collect();
+                self.alloc(HittableList::from_vec(faces))
+            }
+            "triangle" => {
+                value.check_fields(&["type", "vertices", "material"])?;
+                let vertices = value.field("vertices")?;
+                let [a, b, c] = match vertices.as_array()? {
+                    [a, b, c] => [point(a)?, point(b)?, point(c)?],
+                    _ => {
+                        return Err(JsonError::new(
+                            vertices.line,
+                            "a triangle needs exactly 3 vertices",
+                        )
+                        .into())
+                    }
+                };
+                let material = self.material(value.field("material")?)?;
+                self.alloc(Triangle::new(a, b, c, material))
+            }
+            "mesh" => {
+                value.check_fields(&["type", "path"])?;
+                let path = self.dir.join(value.field("path")?.as_str()?);
+                obj::load(path, self.alloc)?
+            }
+            "list" => {
+                value.check_fields(&["type", "objects"])?;
+                let objects = self.objects(value.field("objects")?)?;
+                self.group(objects, None)
+            }
+            "bvh" => {
+                value.check_fields(&["type", "strategy", "objects"])?;
+                let strategy = match value.get("strategy") {
+                    Some(strategy) => bvh_strategy(strategy)?,
+                    None => BvhStrategy::Median,
+                };
+                let objects = self.objects(value.field("objects")?)?;
+                self.group(objects, Some(strategy))
+            }
+            "translate" => {
+                value.check_fields(&["type", "offset", "object"])?;
+                let offset = vector(value.field("offset")?)?;
+                let first_light = self.lights.len();
+                let object = self.object(value.field("object")?)?;
+                for light in &mut self.lights[first_light..] {
+                    *light = Box::leak(Box::new_in(Translate::new(*light, offset), self.alloc));
+                }
+                self.alloc(Translate::new(object, offset))
+            }
+            "rotate" => {
+                value.check_fields(&["type", "axis", "angle", "object"])?;
+                let angle = value.field("angle")?.as_f32()?;
+                let first_light = self.lights.len();
+                let object = self.object(value.field("object")?)?;
+                let axis = value.field("axis")?;
+                match axis.as_str()? {
+                    "x" => self.rotate::<0>(object, angle, first_light),
+                    "y" => self.rotate::<1>(object, angle, first_light),
+                    "z" => self.rotate::<2>(object, angle, first_light),
+                    other => {
+                        return Err(JsonError::new(
+                            axis.line,
+                            format!("unknown axis `{other}` (expected x, y or z)"),
+                        )
+                        .into())
+                    }
+                }
+            }
+            "transform" => {
+                value.check_fields(&["type", "steps", "object"])?;
+                let first_light = self.lights.len();
+                let object = self.object(value.field("object")?)?;
+                let transform = transform(object, value.field("steps")?)?;
+                for light in &mut self.lights[first_light..] {
+                    let light_transform = Transform::with_matrix(*light, transform.matrix());
+                    *light = Box::leak(Box::new_in(light_transform, self.alloc));
+                }
+                self.alloc(transform)
+            }
+            "motion" => {
+                value.check_fields(&["type", "keyframes", "object"])?;
+                let keyframes = value
+                    .field("keyframes")?
+                    .as_array()?
+                    .iter()
+                    .map(keyframe)
+                    .collect::<Result<Vec<_>>>()?;
+                let first_light = self.lights.len();
+                let object = self.object(value.field("object")?)?;
+                for light in &mut self.lights[first_light..] {
+                    let motion = MotionTransform::new(*light, keyframes.clone());
+                    *light = Box::leak(Box::new_in(motion, self.alloc));
+                }
+                self.alloc(MotionTransform::new(object, keyframes))
+            }
+            other => return Err(unknown_type(value, "object", other)),
+        };
+
+        Ok(object)
+    }
+
+    // `{ "type": "linear" | "catmull_rom" | "bezier", "keys": [{ "time": t, "point": [x, y, z] }],
+    // "easing": "ease_in_out" }`; Bezier paths also take the inner control points of every
+    // segment as `"handles": [[[x, y, z], [x, y, z]], ...]`
+    fn sphere_on_path(
+        &self,
+        path: &Value,
+        radius: f32,
+        material: &'a dyn Material,
+    ) -> Result<&'a dyn Hittable> {
+        path.check_fields(&["type", "keys", "handles", "easing"])?;
+        let (keys, easing) = (path_keys(path)?, path_easing(path)?);
+
+        let sphere: &'a dyn Hittable = match type_of(path)? {
+            "linear" => {
+                let keys = Keyframes::new(keys, easing);
+                self.alloc(Sphere::<PiecewiseLinear>::new(keys, radius, material))
+            }
+            "catmull_rom" => {
+                let path = SplinePath::catmull_rom(keys, easing);
+                self.alloc(Sphere::<Spline>::new(path, radius, material))
+            }
+            "bezier" => {
+                let handles = path.field("handles")?;
+                let handles = handles
+                    .as_array()?
+                    .iter()
+                    .map(|pair| match pair.as_array()? {
+                        [a, b] => Ok([point(a)?, point(b)?]),
+                        _ => Err(JsonError::new(pair.line, "expected a pair of points").into()),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if handles.len() + 1 != keys.len() {
+                    return Err(JsonError::new(
+                        path.line,
+                        format!(
+                            "a Bezier path through {} keys needs {} pairs of handles",
+                            keys.len(),
+                            keys.len() - 1
+                        ),
+                    )
+                    .into());
+                }
+
+                let path = SplinePath::bezier(keys, handles, easing);
+                self.alloc(Sphere::<Spline>::new(path, radius, material))
+            }
+            other => return Err(unknown_type(path, "path", other)),
+        };
+
+        Ok(sphere)
+    }
+
+    // also rotates the lights collected from `object`, which start at `first_light`
+    fn rotate<const AXIS: usize>(
+        &mut self,
+        object: &'a dyn Hittable,
+        angle: f32,
+        first_light: usize,
+    ) -> &'a dyn Hittable {
+        for light in &mut self.lights[first_light..] {
+            *light = Box::leak(Box::new_in(Rotate::<AXIS>::new(*light, angle), self.alloc));
+        }
+        self.alloc(Rotate::<AXIS>::new(object, angle))
+    }
+}
+
+// the `"keys": [{ "time": t, "point": [x, y, z] }]` of a path, at least one of them
+fn path_keys(path: &Value) -> Result<Vec<(f32, Point)>> {
+    let keys = path.field("keys")?;
+    let keys = keys
+        .as_array()?
+        .iter()
+        .map(|key| {
+            key.check_fields(&["time", "point"])?;
+            Ok((key.field("time")?.as_f32()?, point(key.field("point")?)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        return Err(JsonError::new(path.line, "a path needs at least one key").into());
+    }
+
+    Ok(keys)
+}
+
+fn path_easing(path: &Value) -> Result<Easing> {
+    let Some(easing) = path.get("easing") else {
+        return Ok(Easing::Linear);
+    };
+
+    let name = easing.as_str()?;
+    Easing::from_name(name).ok_or_else(|| {
+        JsonError::new(
+            easing.line,
+            format!("unknown easing `{name}` (expected linear, ease_in, ease_out or ease_in_out)"),
+        )
+        .into()
+    })
+}
+
+// `lookfrom` and `lookat` are either a point or a linear path through keyframes,
+// `{ "keys": [{ "time": t, "point": [x, y, z] }], "easing": "ease_in_out" }`
+fn camera_point(value: &Value) -> Result<CameraPoint> {
+    if value.as_array().is_ok() {
+        return Ok(CameraPoint::Fixed(point(value)?));
+    }
+
+    value.check_fields(&["keys", "easing"])?;
+    let keyframes = Keyframes::new(path_keys(value)?, path_easing(value)?);
+    Ok(CameraPoint::Moving(keyframes))
+}
+
+enum CameraPoint {
+    Fixed(Point),
+    Moving(Keyframes),
+}
+
+// `{ "threshold": t, "max_samples": n }`
+fn adaptive_sampling(value: &Value) -> Result<AdaptiveSampling> {
+    value.check_fields(&["threshold", "max_samples"])?;
+    let threshold = value.field("threshold")?;
+    let line = threshold.line;
+    let threshold = threshold.as_f32()?;
+    if threshold <= 0. {
+        return Err(JsonError::new(line, "the error threshold has to be positive").into());
+    }
+
+    let max_samples = value.field("max_samples")?;
+    let line = max_samples.line;
+    let max_samples = max_samples.as_usize()?;
+    if max_samples == 0 {
+        return Err(JsonError::new(line, "adaptive sampling needs at least one sample").into());
+    }
+
+    Ok(AdaptiveSampling {
+        threshold,
+        max_samples,
+    })
+}
+
+// `"box"`, `"triangle"` or `{ "type": "trapezoid", "ramp": r }`
+fn shutter_curve(value: &Value) -> Result<ShutterCurve> {
+    if let Ok(name) = value.as_str() {
+        return ShutterCurve::from_name(name).ok_or_else(|| {
+            JsonError::new(
+                value.line,
+                format!("unknown shutter curve `{name}` (expected box, triangle or trapezoid)"),
+            )
+            .into()
+        });
+    }
+
+    value.check_fields(&["type", "ramp"])?;
+    match type_of(value)? {
+        "trapezoid" => {
+            let ramp = value.field("ramp")?;
+            let line = ramp.line;
+            let ramp = ramp.as_f32()?;
+            if !(0. ..=0.5).contains(&ramp) {
+                return Err(
+                    JsonError::new(line, "the shutter ramp has to be within [0, 0.5]").into(),
+                );
+            }
+            Ok(ShutterCurve::Trapezoid(ramp))
+        }
+        other => Err(unknown_type(value, "shutter curve", other)),
+    }
+}
+
+// `"box"`, `"tent"`, `"gaussian"`, `"mitchell"` or `"lanczos"` with their usual parameters, or
+// `{ "type": "gaussian", "radius": r, "sigma": s }` and `{ "type": "mitchell", "b": b, "c": c }`
+// to change them; every filter takes a radius
+fn filter(value: &Value) -> Result<Filter> {
+    let name = match value.as_str() {
+        Ok(name) => name,
+        Err(_) => type_of(value)?,
+    };
+    let Some(mut filter) = Filter::from_name(name) else {
+        return Err(JsonError::new(
+            value.line,
+            format!("unknown filter `{name}` (expected box, tent, gaussian, mitchell or lanczos)"),
+        )
+        .into());
+    };
+    if value.as_str().is_ok() {
+        return Ok(filter);
+    }
+
+    match &mut filter {
+        Filter::Gaussian { sigma, .. } => {
+            value.check_fields(&["type", "radius", "sigma"])?;
+            if let Some(value) = value.get("sigma") {
+                *sigma = value.as_f32()?;
+                if *sigma <= 0. {
+                    return Err(JsonError::new(value.line, "sigma has to be positive").into());
+                }
+            }
+        }
+        Filter::Mitchell { b, c, .. } => {
+            value.check_fields(&["type", "radius", "b", "c"])?;
+            if let Some(value) = value.get("b") {
+                *b = value.as_f32()?;
+            }
+            if let Some(value) = value.get("c") {
+                *c = value.as_f32()?;
+            }
+        }
+        _ => value.check_fields(&["type", "radius"])?,
+    }
+    if let Some(radius) = value.get("radius") {
+        let line = radius.line;
+        let radius = radius.as_f32()?;
+        if radius < 0.5 {
+            return Err(
+                JsonError::new(line, "the filter radius has to be at least half a pixel").into(),
+            );
+        }
+        filter = filter.with_radius(radius);
+    }
+
+    Ok(filter)
+}
+
+fn aovs(value: &Value) -> Result<Vec<Aov>> {
+    let mut aovs = Vec::new();
+    for value in value.as_array()? {
+        let name = value.as_str()?;
+        let aov = Aov::from_name(name).ok_or_else(|| {
+            let names = Aov::ALL.map(Aov::name).join(", ");
+            JsonError::new(
+                value.line,
+                format!("unknown AOV `{name}` (expected one of: {names})"),
+            )
+        })?;
+        if !aovs.contains(&aov) {
+            aovs.push(aov);
+        }
+    }
+
+    Ok(aovs)
+}
+
+fn type_of(value: &Value) -> Result<&str> {
+    Ok(value.field("type")?.as_str()?)
+}
+
+fn unknown_type(value: &Value, what: &str, name: &str) -> Error {
+    JsonError::new(value.line, format!("unknown {what} type `{name}`")).into()
+}
+
+// `steps` is a list of `{ "translate": [x, y, z] }`, `{ "scale": [x, y, z] }` (or a single
+// number) and `{ "rotate": [x, y, z], "angle": degrees }`, applied in order
+fn transform<'a>(object: &'a dyn Hittable, steps: &Value) -> Result<Transform<'a>> {
+    let mut transform = Transform::new(object);
+    for step in steps.as_array()? {
+        transform = if let Some(offset) = step.get("translate") {
+            step.check_fields(&["translate"])?;
+            transform.translate(vector(offset)?)
+        } else if let Some(scale) = step.get("scale") {
+            step.check_fields(&["scale"])?;
+            transform.scale(scale_vector(scale)?)
+        } else if let Some(axis) = step.get("rotate") {
+            step.check_fields(&["rotate", "angle"])?;
+            let angle = step.field("angle")?.as_f32()?;
+            transform.rotate(vector(axis)?, angle)
+        } else {
+            return Err(JsonError::new(
+                step.line,
+                "expected a `translate`, `scale` or `rotate` step",
+            )
+            .into());
+        };
+    }
+
+    Ok(transform)
+}
+
+// `{ "time": t, "translate": [x, y, z], "rotate": [x, y, z], "angle": degrees, "scale": s }`,
+// where everything but the time is optional
+fn keyframe(value: &Value) -> Result<Keyframe> {
+    value.check_fields(&["time", "translate", "rotate", "angle", "scale"])?;
+    let mut keyframe = Keyframe::new(value.field("time")?.as_f32()?);
+    if let Some(scale) = value.get("scale") {
+        keyframe = keyframe.scale(scale_vector(scale)?);
+    }
+    if let Some(axis) = value.get("rotate") {
+        let angle = value.field("angle")?.as_f32()?;
+        keyframe = keyframe.rotate(vector(axis)?, angle);
+    }
+    if let Some(offset) = value.get("translate") {
+        keyframe = keyframe.translate(vector(offset)?);
+    }
+
+    Ok(keyframe)
+}
+
+// either `[x, y, z]` or a single number for uniform scaling
+fn scale_vector(value: &Value) -> Result<Vector> {
+    match value.as_f32() {
+        Ok(scale) => Ok(Vector::splat(scale)),
+        Err(_) => vector(value),
+    }
+}
+
+fn bvh_strategy(value: &Value) -> Result<BvhStrategy> {
+    let name = value.as_str()?;
+    BvhStrategy::from_name(name).ok_or_else(|| {
+        JsonError::new(
+            value.line,
+            format!("unknown BVH strategy `{name}` (expected median or sah)"),
+        )
+        .into()
+    })
+}
+
+fn point(value: &Value) -> Result<Point> {
+    let [x, y, z] = value.as_f32_array()?;
+    Ok(Point::new(x, y, z))
+}
+
+fn vector(value: &Value) -> Result<Vector> {
+    Ok(Vector::from_array(value.as_f32_array()?))
+}
+
+fn color(value: &Value) -> Result<Color> {
+    Ok(Color::from_array(value.as_f32_array()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::Global;
+
+    use super::*;
+
+    fn error(source: &str) -> String {
+        match parse("scenes/test.json", source, Global) {
+            Ok(_) => panic!("expected an error loading\n{source}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn cornell_box_loads() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cornell_box.json");
+        let scene = load(&path, Global).unwrap();
+        assert_eq!(scene.lights.len(), 1);
+
+        let camera = scene.camera.build();
+        assert_eq!((camera.image_width(), camera.image_height()), (600, 600));
+        assert_eq!(camera.samples_per_pixel(), 200);
+    }
+
+    #[test]
+    fn unknown_fields_point_at_their_line() {
+        let err = error(
+            r#"{
+                "objects": [],
+                "lights": []
+            }"#,
+        );
+        assert_eq!(
+            err,
+            "scenes/test.json:3: unknown field `lights` (expected one of: camera, textures, \
+             materials, objects, bvh)"
+        );
+
+        let err = error(
+            r#"{
+                "camera": {
+                    "fov": 40
+                },
+                "objects": []
+            }"#,
+        );
+        assert_eq!(err, "scenes/test.json:3: unknown camera setting `fov`");
+
+        let err = error(
+            r#"{
+                "objects": [
+                    {
+                        "type": "sphere",
+                        "center": [0, 0, 0],
+                        "radius": 1,
+                        "materail": "white"
+                    }
+                ]
+            }"#,
+        );
+        assert!(
+            err.starts_with("scenes/test.json:7: unknown field `materail`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn bad_values_point_at_their_line() {
+        let camera_error = |setting: &str| {
+            error(&format!(
+                r#"{{
+                    "camera": {{
+
+                        {setting}
+                    }},
+                    "objects": []
+                }}"#
+            ))
+        };
+        assert_eq!(
+            camera_error(r#""image_width": "wide""#),
+            "scenes/test.json:4: expected a number, found a string"
+        );
+        assert_eq!(
+            camera_error(r#""samples_per_pixel": -4"#),
+            "scenes/test.json:4: expected a non-negative integer, found a number"
+        );
+        assert_eq!(
+            camera_error(r#""lookat": [1, 2]"#),
+            "scenes/test.json:4: expected 3 numbers, found 2"
+        );
+        assert_eq!(
+            camera_error(r#""adaptive": { "threshold": 0.1, "max_samples": 0 }"#),
+            "scenes/test.json:4: adaptive sampling needs at least one sample"
+        );
+
+        let err = error(
+            r#"{
+                "objects": [
+                    { "type": "sphere", "center": [0, 0, 0], "radius": 1,
+                      "material": "missing" }
+                ]
+            }"#,
+        );
+        assert_eq!(err, "scenes/test.json:4: unknown material `missing`");
+    }
+
+    #[test]
+    fn syntax_errors_point_at_their_line() {
+        let err = error(
+            r#"{
+                "objects": [
+                    1,
+                    2
+                    3
+                ]
+            }"#,
+        );
+        assert_eq!(err, "scenes/test.json:5: expected `,` or `]` in array");
+
+        let err = error("{ \"objects\": [] }\n}");
+        assert_eq!(
+            err,
+            "scenes/test.json:2: trailing characters after the top-level value"
+        );
+    }
+}
@@ -1,56 +1,291 @@
 #![feature(allocator_api)]
 
-use std::alloc::Global;
+use cli::{Command, SceneSource};
+
+use std::{
+    alloc::Global,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
 
 use bumpalo::Bump;
-use rand::{thread_rng, Rng};
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::{Rng, SeedableRng};
 
 use raytracing::{
-    camera::CameraBuilder,
+    aov::Aov,
+    camera::{AdaptiveSampling, CameraBuilder, Progressive},
+    film::Film,
     hittable::{
-        bvh::BvhNode,
+        bvh::Bvh,
         instances::{Rotate, Translate},
         quad::Quad,
         Hittable, HittableList, Sphere,
     },
+    loader::scene::{self, Scene},
     material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    output::{self, OutputFormat},
+    progress::{Progress, RenderObserver},
     texture::{perlin::NoiseTexture, GlobalChecker, ImageTexture, SolidColor},
+    tiles::Tile,
     time_utils::{Linear, Unchanging},
-    units::{Color, Point, Vector},
+    tonemap::ToneMapper,
+    units::{Color, Point, RenderRng, Vector},
 };
 
+mod cli;
+
+type Lights = Vec<&'static dyn Hittable>;
+type SceneFn = fn(&mut CameraBuilder, &mut Lights, &mut RenderRng) -> &'static dyn Hittable;
+
+const SCENES: [(&str, SceneFn); 7] = [
+    ("random-spheres", |_, _, rng| random_spheres(rng)),
+    ("two-spheres", |_, _, _| two_spheres()),
+    ("earth", |_, _, _| earth()),
+    ("two-perlin-spheres", |_, _, rng| two_perlin_spheres(rng)),
+    ("quads", |camera, _, _| quads(camera)),
+    ("simple-light", simple_light),
+    ("cornell-box", |camera, lights, _| {
+        cornell_box(camera, lights)
+    }),
+];
+
 fn main() {
-    let mut camera = CameraBuilder::default();
-    camera
-        .with_aspect_ratio(16. / 9.)
-        .with_image_width(400)
-        .with_vfov(20.)
-        .with_lookfrom(Point::new(13., 2., 3.))
-        .with_lookat(Point::new(0., 0., 0.))
-        .with_vup(Vector::new(0., 1., 0.))
-        .with_defocus_angle(0.6)
-        .with_focus_dist(10.)
-        .with_samples_per_pixel(100)
-        .with_max_depth(50)
-        .with_background(Color::new(0.7, 0.8, 1.0));
-
-    let world = match 7 {
-        1 => random_spheres(),
-        2 => two_spheres(),
-        3 => earth(),
-        4 => two_perlin_spheres(),
-        5 => quads(&mut camera),
-        6 => simple_light(&mut camera),
-        7 => cornell_box(&mut camera),
-        _ => unimplemented!(),
+    let names = SCENES.map(|(name, _)| name);
+    let mut args = match cli::parse(std::env::args().skip(1), &names) {
+        Ok(Command::Render(args)) => args,
+        Ok(Command::ListScenes) => {
+            println!("{}", names.join("\n"));
+            return;
+        }
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("failed to set up the thread pool");
+    }
+
+    let seed = args.seed.unwrap_or(0);
+    let mut rng = RenderRng::seed_from_u64(seed);
+
+    let (mut camera, world, lights) = match &args.scene {
+        SceneSource::Builtin(name) => {
+            let mut camera = CameraBuilder::default();
+            camera
+                .with_aspect_ratio(16. / 9.)
+                .with_image_width(400)
+                .with_vfov(20.)
+                .with_lookfrom(Point::new(13., 2., 3.))
+                .with_lookat(Point::new(0., 0., 0.))
+                .with_vup(Vector::new(0., 1., 0.))
+                .with_defocus_angle(0.6)
+                .with_focus_dist(10.)
+                .with_samples_per_pixel(100)
+                .with_max_depth(50)
+                .with_background(Color::new(0.7, 0.8, 1.0));
+
+            let (_, build) = SCENES.iter().find(|(n, _)| n == name).unwrap();
+            let mut lights = Vec::new();
+            let world = build(&mut camera, &mut lights, &mut rng);
+            (camera, world, lights)
+        }
+        SceneSource::File(path) => match scene::load(path, leak(Bump::new())) {
+            Ok(Scene {
+                camera,
+                world,
+                lights,
+            }) => (camera, world, lights),
+            Err(err) => {
+                eprintln!("error: {err}");
+                process::exit(1);
+            }
+        },
     };
 
+    if let Some(width) = args.width {
+        camera.with_image_width(width);
+    }
+    if let Some(height) = args.height {
+        camera.with_image_height(height);
+    }
+    if let Some(samples_per_pixel) = args.samples_per_pixel {
+        camera.with_samples_per_pixel(samples_per_pixel);
+    }
+    if let Some(max_depth) = args.max_depth {
+        camera.with_max_depth(max_depth);
+    }
+    if let Some(seed) = args.seed {
+        camera.with_seed(seed);
+    }
+    if let Some(frames) = args.frames {
+        camera.with_frames(frames);
+    }
+    if let Some(threshold) = args.adaptive {
+        camera.with_adaptive_sampling(AdaptiveSampling {
+            threshold,
+            max_samples: args.max_samples.unwrap_or(1024),
+        });
+    }
+    if let Some(sampler) = args.sampler.take() {
+        camera.with_sampler(sampler);
+    }
+    if let Some(filter) = args.filter {
+        camera.with_filter(filter);
+    }
+    if !args.aovs.is_empty() {
+        camera.with_aovs(&args.aovs);
+    }
+    if let Some(tile_size) = args.tile_size {
+        camera.with_tile_size(tile_size);
+    }
+    if let Some(tile_order) = args.tile_order {
+        camera.with_tile_order(tile_order);
+    }
+
     let camera = camera.build();
 
-    camera.render(world);
+    // a time limit alone keeps refining until the time is up
+    let progressive = (args.progressive || args.time_limit.is_some()).then(|| Progressive {
+        pass_samples: args.pass_samples.unwrap_or(1),
+        max_samples: match (args.time_limit, args.samples_per_pixel) {
+            (Some(_), None) => None,
+            _ => Some(camera.max_samples()),
+        },
+        time_limit: args.time_limit,
+    });
+
+    // (frame, output) for every image to render
+    let images = if camera.frames() == 1 && args.frame_range.is_none() {
+        vec![(0, args.output.unwrap_or_else(|| PathBuf::from("image.png")))]
+    } else {
+        let (first, last) = args.frame_range.unwrap_or((1, camera.frames()));
+        if last > camera.frames() {
+            eprintln!(
+                "error: cannot render frame {last} of an animation with {} frames",
+                camera.frames()
+            );
+            process::exit(2);
+        }
+
+        let output = args.output.unwrap_or_else(|| PathBuf::from("frame.png"));
+        // frame sequences usually go in a directory of their own
+        if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            if let Err(err) = fs::create_dir_all(dir) {
+                eprintln!("error: failed to create {}: {err}", dir.display());
+                process::exit(1);
+            }
+        }
+
+        (first..=last)
+            .map(|frame| (frame - 1, output::frame_path(&output, frame)))
+            .collect()
+    };
+
+    for (frame, output) in images {
+        if camera.frames() > 1 {
+            eprintln!("Frame {}/{}", frame + 1, camera.frames());
+        }
+
+        let observer = TerminalObserver {
+            bar: ProgressBar::new(0).with_style(
+                ProgressStyle::with_template("[{elapsed_precise}] {wide_bar} {pos}/{len} {msg}")
+                    .unwrap(),
+            ),
+            preview: args.save_passes.then_some((output.as_path(), args.format)),
+            tone_mapper: args.tone_mapper,
+        };
+        let film = match progressive {
+            Some(progressive) => {
+                camera.render_progressive(world, &lights, frame, progressive, &observer)
+            }
+            None => camera.render_frame(world, &lights, frame, &observer),
+        };
+        save(&film, &output, args.format, &args.tone_mapper);
+
+        if let Some(sample_map) = &args.sample_map {
+            let path = if camera.frames() > 1 {
+                output::frame_path(sample_map, frame + 1)
+            } else {
+                sample_map.clone()
+            };
+            save_sample_map(&film, &path);
+        }
+
+        // EXRs already have them as layers
+        if args.format != OutputFormat::Exr {
+            for aov in film.aovs() {
+                let path = output::aov_path(&output, aov);
+                save_aov(&film, aov, &path, args.format, &args.tone_mapper);
+            }
+        }
+    }
+    eprintln!("Done.");
+}
+
+// counts finished tiles on stderr, and optionally saves the image after every pass
+struct TerminalObserver<'a> {
+    bar: ProgressBar,
+    preview: Option<(&'a Path, OutputFormat)>,
+    tone_mapper: ToneMapper,
+}
+
+impl RenderObserver for TerminalObserver<'_> {
+    fn started(&self, tiles: &[Tile]) {
+        self.bar.set_length(tiles.len() as u64);
+    }
+
+    fn tile_finished(&self, _tile: &Tile, progress: Progress, _film: &Film) {
+        self.bar.set_position(progress.tiles_done as u64);
+        if progress.pass > 0 {
+            self.bar.set_message(format!("pass {}", progress.pass + 1));
+        }
+    }
+
+    fn pass_finished(&self, _pass: usize, film: &Film) {
+        if let Some((output, format)) = self.preview {
+            save(film, output, format, &self.tone_mapper);
+        }
+    }
+
+    fn finished(&self, _film: &Film) {
+        self.bar.finish();
+    }
+}
+
+fn save(film: &Film, path: &Path, format: OutputFormat, tone_mapper: &ToneMapper) {
+    if let Err(err) = output::save_tone_mapped(film, path, format, tone_mapper) {
+        eprintln!("error: failed to write {}: {err}", path.display());
+        process::exit(1);
+    }
+}
+
+fn save_sample_map(film: &Film, path: &Path) {
+    let format = OutputFormat::from_path(path).unwrap_or(OutputFormat::Png);
+    if let Err(err) = output::save_sample_map(film, path, format) {
+        eprintln!("error: failed to write {}: {err}", path.display());
+        process::exit(1);
+    }
 }
 
-fn cornell_box(camera: &mut CameraBuilder) -> &'static dyn Hittable {
+fn save_aov(film: &Film, aov: Aov, path: &Path, format: OutputFormat, tone_mapper: &ToneMapper) {
+    if let Err(err) = output::save_aov(film, aov, path, format, tone_mapper) {
+        eprintln!("error: failed to write {}: {err}", path.display());
+        process::exit(1);
+    }
+}
+
+fn cornell_box(camera: &mut CameraBuilder, lights: &mut Lights) -> &'static dyn Hittable {
     let bump = leak(Bump::new());
     let mut world = HittableList::with_capacity(12);
 
@@ -83,12 +318,14 @@ fn cornell_box(camera: &mut CameraBuilder) -> &'static dyn Hittable {
         Vector::new(0.0, 0.0, 555.0),
         red,
     )));
-    world.add(bump.alloc(Quad::new(
+    let light = bump.alloc(Quad::new(
         Point::new(343.0, 554.0, 332.0),
         Vector::new(-130.0, 0.0, 0.0),
         Vector::new(0.0, 0.0, -105.0),
         light,
-    )));
+    ));
+    world.add(light);
+    lights.push(light);
     world.add(bump.alloc(Quad::new(
         Point::new(0.0, 0.0, 0.0),
         Vector::new(0.0, 0.0, 555.0),
@@ -142,10 +379,14 @@ fn cornell_box(camera: &mut CameraBuilder) -> &'static dyn Hittable {
     bump.alloc(world)
 }
 
-fn simple_light(camera: &mut CameraBuilder) -> &'static dyn Hittable {
+fn simple_light(
+    camera: &mut CameraBuilder,
+    lights: &mut Lights,
+    rng: &mut RenderRng,
+) -> &'static dyn Hittable {
     let mut world = HittableList::with_capacity(3);
 
-    let noise = leak(NoiseTexture::new(4.0));
+    let noise = leak(NoiseTexture::new(4.0, rng));
     let sphere_mat = leak(Lambertian { albedo: noise });
     let light_mat = leak(DiffuseLight::new_with_color(
         Color::new(4.0, 4.0, 4.0),
@@ -163,17 +404,20 @@ fn simple_light(camera: &mut CameraBuilder) -> &'static dyn Hittable {
         sphere_mat,
     )));
 
-    world.add(leak(Sphere::<Unchanging>::new(
+    let light_sphere = leak(Sphere::<Unchanging>::new(
         Point::new(0.0, 7.0, 0.0),
         2.0,
         light_mat,
-    )));
-    world.add(leak(Quad::new(
+    ));
+    let light_quad = leak(Quad::new(
         Point::new(3.0, 1.0, -2.0),
         Vector::new(2.0, 0.0, 0.0),
         Vector::new(0.0, 2.0, 0.0),
         light_mat,
-    )));
+    ));
+    world.add(light_sphere);
+    world.add(light_quad);
+    lights.extend([light_sphere as &dyn Hittable, light_quad]);
 
     camera
         .with_lookfrom(Point::new(26.0, 3.0, 6.0))
@@ -238,10 +482,10 @@ fn quads(camera: &mut CameraBuilder) -> &'static dyn Hittable {
     leak(world)
 }
 
-fn two_perlin_spheres() -> &'static dyn Hittable {
+fn two_perlin_spheres(rng: &mut RenderRng) -> &'static dyn Hittable {
     let mut world = HittableList::with_capacity(2);
 
-    let perlin = leak(NoiseTexture::new(4.0));
+    let perlin = leak(NoiseTexture::new(4.0, rng));
     let material = leak(Lambertian { albedo: perlin });
 
     world.add(leak(Sphere::<Unchanging>::new(
@@ -296,9 +540,8 @@ fn two_spheres() -> &'static dyn Hittable {
     leak(world)
 }
 
-fn random_spheres() -> &'static dyn Hittable {
+fn random_spheres(rng: &mut RenderRng) -> &'static dyn Hittable {
     let bump: &_ = leak(Bump::new());
-    let mut rng = thread_rng();
 
     let mut objects: Vec<&dyn Hittable> = Vec::with_capacity(124);
     let ground_material = bump.alloc(Lambertian {
@@ -381,7 +624,7 @@ fn random_spheres() -> &'static dyn Hittable {
         }),
     )));
 
-    let world = BvhNode::new(objects, bump);
+    let world = Bvh::new(objects);
 
     bump.alloc(world)
 }
@@ -395,3 +638,35 @@ fn lambertian_with_color(color: Color) -> &'static Lambertian<'static> {
         albedo: leak(SolidColor { color }),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // renders a small, noisy version of the scene, which still tells different scenes apart
+    fn render(mut camera: CameraBuilder, world: &dyn Hittable, lights: &[&dyn Hittable]) -> Film {
+        camera
+            .with_image_width(24)
+            .with_samples_per_pixel(8)
+            .with_max_depth(8)
+            .with_seed(3);
+        camera.build().render(world, lights)
+    }
+
+    #[test]
+    fn cornell_box_file_matches_the_built_in_scene() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cornell_box.json");
+        let scene = scene::load(path, leak(Bump::new())).unwrap();
+
+        let mut camera = CameraBuilder::default();
+        let mut lights = Vec::new();
+        let world = cornell_box(&mut camera, &mut lights);
+        assert_eq!(scene.lights.len(), lights.len());
+
+        let expected = render(camera, world, &lights);
+        let film = render(scene.camera, scene.world, &scene.lights);
+        assert_eq!(film.width(), expected.width());
+        assert_eq!(film.height(), expected.height());
+        assert!(film.pixels() == expected.pixels());
+    }
+}
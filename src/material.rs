@@ -1,21 +1,66 @@
-use std::alloc::Allocator;
+use std::{alloc::Allocator, f32::consts::PI};
 
-use rand::random;
+use rand::Rng;
 
 use crate::{
     hittable::HitRecord,
     ray::Ray,
+    sampler::SampleRng,
     texture::{SolidColor, Texture},
-    units::{random_unit_vector, reflect, refract, Color},
+    units::{random_unit_vector, reflect, refract, Color, Vector},
 };
 
-pub struct MatRecord {
-    pub scatter: Option<(Color, Ray)>,
-    pub emit: Option<Color>,
+pub struct BsdfSample {
+    pub direction: Vector,
+    // the BSDF times the cosine term, divided by `pdf`
+    pub weight: Color,
+    pub pdf: f32,
+    // set for perfectly specular lobes, whose `pdf` is a dirac delta: `eval` and `pdf` are zero
+    // for every direction, so such samples can't be combined with light sampling
+    pub delta: bool,
 }
 
+// All directions point away from the surface, except `ray.direct`, which points at it.
 pub trait Material: Sync {
-    fn hit_info(&self, ray: &Ray, hit: &HitRecord) -> MatRecord;
+    // picks the direction to continue a path in; `None` absorbs the path
+    fn sample(&self, _ray: &Ray, _hit: &HitRecord, _rng: &mut SampleRng) -> Option<BsdfSample> {
+        None
+    }
+
+    // the BSDF times the cosine term for light scattered from `direction` into `-ray.direct`
+    fn eval(&self, _ray: &Ray, _hit: &HitRecord, _direction: Vector) -> Color {
+        Color::ZERO
+    }
+
+    // the solid angle density with which `sample` picks `direction`
+    fn pdf(&self, _ray: &Ray, _hit: &HitRecord, _direction: Vector) -> f32 {
+        0.
+    }
+
+    fn emitted(&self, _ray: &Ray, _hit: &HitRecord) -> Color {
+        Color::ZERO
+    }
+
+    // the fraction of light the surface reflects, for the albedo AOV
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        Color::ZERO
+    }
+
+    // for the material ID AOV; 0 is for materials without one
+    fn id(&self) -> u32 {
+        0
+    }
+}
+
+impl BsdfSample {
+    pub fn delta(direction: Vector, weight: Color) -> Self {
+        Self {
+            direction,
+            weight,
+            pdf: 1.,
+            delta: true,
+        }
+    }
 }
 
 pub struct Lambertian<'a> {
@@ -35,6 +80,12 @@ pub struct DiffuseLight<'a> {
     pub emit: &'a dyn Texture,
 }
 
+// A material with an ID for the material ID AOV, which otherwise behaves like the one it wraps.
+pub struct Identified<'a> {
+    pub material: &'a dyn Material,
+    pub id: u32,
+}
+
 impl<'a> Lambertian<'a> {
     pub fn new_with_color<A: Allocator + 'a>(color: Color, alloc: A) -> Self {
         let color = Box::leak(Box::new_in(SolidColor { color }, alloc));
@@ -43,45 +94,55 @@ impl<'a> Lambertian<'a> {
 }
 
 impl Material for Lambertian<'_> {
-    fn hit_info(&self, ray: &Ray, hit: &HitRecord) -> MatRecord {
-        let direct = {
-            let direct = hit.normal + random_unit_vector();
-            if direct.abs().max_element() < f32::EPSILON {
+    // cosine weighted, so the weight is just the albedo
+    fn sample(&self, ray: &Ray, hit: &HitRecord, rng: &mut SampleRng) -> Option<BsdfSample> {
+        let direction = {
+            let direction = hit.normal + random_unit_vector(rng);
+            if direction.abs().max_element() < f32::EPSILON {
                 hit.normal
             } else {
-                direct
+                direction
             }
         };
 
-        let scattered = Ray {
-            origin: hit.p,
-            direct,
-            ..*ray
-        };
+        let pdf = self.pdf(ray, hit, direction);
+        if pdf <= 0. {
+            return None;
+        }
 
-        let color = self.albedo.value(hit.uv, hit.p);
+        Some(BsdfSample {
+            direction,
+            weight: self.albedo.value(hit.uv, hit.p),
+            pdf,
+            delta: false,
+        })
+    }
 
-        MatRecord {
-            scatter: Some((color, scattered)),
-            emit: None,
-        }
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: Vector) -> Color {
+        self.albedo.value(hit.uv, hit.p) * self.pdf(ray, hit, direction)
+    }
+
+    fn pdf(&self, _ray: &Ray, hit: &HitRecord, direction: Vector) -> f32 {
+        let cosine = hit.normal.dot(direction.normalize());
+        cosine.max(0.) / PI
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.albedo.value(hit.uv, hit.p)
     }
 }
 
 impl Material for Metal {
-    fn hit_info(&self, ray: &Ray, hit: &HitRecord) -> MatRecord {
+    // the fuzzed reflection has no closed form density, so it is treated as a delta lobe too
+    fn sample(&self, ray: &Ray, hit: &HitRecord, rng: &mut SampleRng) -> Option<BsdfSample> {
         let reflected = reflect(&ray.direct, &hit.normal);
+        let direction = reflected + random_unit_vector(rng) * self.fuzz;
 
-        let scattered = Ray {
-            origin: hit.p,
-            direct: reflected + random_unit_vector() * self.fuzz,
-            time: ray.time,
-        };
+        Some(BsdfSample::delta(direction, self.albedo))
+    }
 
-        MatRecord {
-            scatter: Some((self.albedo, scattered)),
-            emit: None,
-        }
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        self.albedo
     }
 }
 
@@ -94,8 +155,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn hit_info(&self, ray: &Ray, hit: &HitRecord) -> MatRecord {
-        let attenuation = Color::ONE;
+    fn sample(&self, ray: &Ray, hit: &HitRecord, rng: &mut SampleRng) -> Option<BsdfSample> {
         let refraction_ratio = if hit.front_face {
             self.ir.recip()
         } else {
@@ -108,24 +168,20 @@ impl Material for Dielectric {
 
         let cannot_refract = refraction_ratio * sin_theta > 1.;
         let cannot_refract =
-            cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random();
+            cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > rng.gen();
 
-        let direct = if cannot_refract {
+        let direction = if cannot_refract {
             reflect(&unit_direction, &hit.normal)
         } else {
             refract(&unit_direction, &hit.normal, refraction_ratio)
         };
 
-        let scattered = Ray {
-            origin: hit.p,
-            direct,
-            time: ray.time,
-        };
+        Some(BsdfSample::delta(direction, Color::ONE))
+    }
 
-        MatRecord {
-            scatter: Some((attenuation, scattered)),
-            emit: None,
-        }
+    // glass absorbs nothing
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        Color::ONE
     }
 }
 
@@ -137,12 +193,33 @@ impl<'a> DiffuseLight<'a> {
 }
 
 impl Material for DiffuseLight<'_> {
-    fn hit_info(&self, _ray: &Ray, hit: &HitRecord) -> MatRecord {
-        let color = self.emit.value(hit.uv, hit.p);
+    fn emitted(&self, _ray: &Ray, hit: &HitRecord) -> Color {
+        self.emit.value(hit.uv, hit.p)
+    }
+}
 
-        MatRecord {
-            scatter: None,
-            emit: Some(color),
-        }
+impl Material for Identified<'_> {
+    fn sample(&self, ray: &Ray, hit: &HitRecord, rng: &mut SampleRng) -> Option<BsdfSample> {
+        self.material.sample(ray, hit, rng)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: Vector) -> Color {
+        self.material.eval(ray, hit, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: Vector) -> f32 {
+        self.material.pdf(ray, hit, direction)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitRecord) -> Color {
+        self.material.emitted(ray, hit)
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.material.albedo(hit)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
     }
 }
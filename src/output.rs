@@ -0,0 +1,361 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, WritableImage};
+use image::{
+    codecs::hdr::HdrEncoder, GrayImage, ImageError, ImageFormat, Rgb, Rgb32FImage, RgbImage,
+};
+
+use crate::{aov::Aov, film::Film, tonemap::ToneMapper, units::Color};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Ppm,
+    Png,
+    Jpeg,
+    Hdr,
+    Exr,
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    UnknownFormat(PathBuf),
+    Io(std::io::Error),
+    Image(ImageError),
+    Exr(exr::error::Error),
+}
+
+impl OutputFormat {
+    pub const ALL: [Self; 5] = [Self::Ppm, Self::Png, Self::Jpeg, Self::Hdr, Self::Exr];
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "ppm" => Some(Self::Ppm),
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "hdr" => Some(Self::Hdr),
+            "exr" => Some(Self::Exr),
+            _ => None,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?;
+        Self::from_extension(ext)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Ppm => "ppm",
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Hdr => "hdr",
+            Self::Exr => "exr",
+        }
+    }
+
+    // whether the format stores linear floats, rather than gamma-encoded 8-bit values
+    pub fn is_linear(self) -> bool {
+        matches!(self, Self::Hdr | Self::Exr)
+    }
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat(path) => write!(
+                f,
+                "cannot infer an output format from {:?} (expected ppm, png, jpg, hdr or exr)",
+                path
+            ),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Image(err) => write!(f, "{err}"),
+            Self::Exr(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+impl From<std::io::Error> for OutputError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ImageError> for OutputError {
+    fn from(err: ImageError) -> Self {
+        Self::Image(err)
+    }
+}
+
+impl From<exr::error::Error> for OutputError {
+    fn from(err: exr::error::Error) -> Self {
+        Self::Exr(err)
+    }
+}
+
+pub fn save<P: AsRef<Path>>(film: &Film, path: P) -> Result<(), OutputError> {
+    let path = path.as_ref();
+    let format =
+        OutputFormat::from_path(path).ok_or_else(|| OutputError::UnknownFormat(path.into()))?;
+
+    save_with_format(film, path, format)
+}
+
+pub fn save_with_format<P: AsRef<Path>>(
+    film: &Film,
+    path: P,
+    format: OutputFormat,
+) -> Result<(), OutputError> {
+    save_tone_mapped(film, path, format, &ToneMapper::default())
+}
+
+// like `save_with_format`, with `tone_mapper` turning the film into 8-bit colors; linear formats
+// keep the film's radiance as it is. EXRs get the film's AOVs as well, see `save_layered_exr`.
+pub fn save_tone_mapped<P: AsRef<Path>>(
+    film: &Film,
+    path: P,
+    format: OutputFormat,
+    tone_mapper: &ToneMapper,
+) -> Result<(), OutputError> {
+    let path = path.as_ref();
+
+    match format {
+        OutputFormat::Ppm => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_ppm(&mut out, film, tone_mapper)?;
+            out.flush()?;
+        }
+        OutputFormat::Png | OutputFormat::Jpeg => {
+            let image = to_rgb8(film, tone_mapper);
+            let format = if format == OutputFormat::Png {
+                ImageFormat::Png
+            } else {
+                ImageFormat::Jpeg
+            };
+            image.save_with_format(path, format)?;
+        }
+        OutputFormat::Exr if !film.aovs().is_empty() => save_layered_exr(film, path)?,
+        OutputFormat::Hdr | OutputFormat::Exr => {
+            save_linear(&film.pixels(), film.width(), film.height(), path, format)?;
+        }
+    }
+
+    Ok(())
+}
+
+// The image and every AOV of `film` in one EXR. The image takes the R, G and B channels, and the
+// AOVs are prefixed with their names, like `depth.Z` and `normal.X`, which compositors read as
+// layers.
+pub fn save_layered_exr<P: AsRef<Path>>(film: &Film, path: P) -> Result<(), OutputError> {
+    let channel = |name: &str, pixels: &[Color], component: usize| {
+        let samples = pixels.iter().map(|c| c[component]).collect();
+        AnyChannel::new(name, FlatSamples::F32(samples))
+    };
+
+    let pixels = film.pixels();
+    let mut channels: Vec<_> = ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(component, name)| channel(name, &pixels, component))
+        .collect();
+    for aov in film.aovs() {
+        let pixels = film.aov_pixels(aov).unwrap();
+        for (component, name) in aov.channels().iter().enumerate() {
+            let name = format!("{}.{name}", aov.name());
+            channels.push(channel(&name, &pixels, component));
+        }
+    }
+
+    let size = (film.width(), film.height());
+    exr::prelude::Image::from_channels(size, AnyChannels::sort(channels.into()))
+        .write()
+        .to_file(path)?;
+
+    Ok(())
+}
+
+// One AOV of `film` as an image of its own. Linear formats get its values as they are; otherwise
+// lighting goes through `tone_mapper` like the image, normals are mapped from [-1, 1], depth is
+// shown as shades of gray up to white for the farthest hit, and every ID gets a color of its own.
+pub fn save_aov<P: AsRef<Path>>(
+    film: &Film,
+    aov: Aov,
+    path: P,
+    format: OutputFormat,
+    tone_mapper: &ToneMapper,
+) -> Result<(), OutputError> {
+    let path = path.as_ref();
+    let pixels = film
+        .aov_pixels(aov)
+        .unwrap_or_else(|| panic!("The film has no {} AOV!", aov.name()));
+    let (width, height) = (film.width(), film.height());
+
+    if format.is_linear() {
+        return save_linear(&pixels, width, height, path, format);
+    }
+
+    let max_depth = pixels.iter().map(|c| c.x).fold(0., f32::max);
+    let to_rgb8 = |c: Color, x: usize, y: usize| -> [u8; 3] {
+        let display = match aov {
+            Aov::Direct | Aov::Indirect | Aov::Emission => return tone_mapper.to_rgb8(c, x, y),
+            Aov::Albedo => return ToneMapper::default().to_rgb8(c, x, y),
+            Aov::Depth => Color::splat(c.x / max_depth.max(f32::MIN_POSITIVE)),
+            Aov::Normal => c * 0.5 + Color::splat(0.5),
+            Aov::Uv => c,
+            Aov::ObjectId | Aov::MaterialId => id_color(c.x as u32),
+        };
+        display
+            .as_array()
+            .map(|v| (v.clamp(0., 1.) * 255.).round() as u8)
+    };
+
+    let data = pixels
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, &c)| to_rgb8(c, idx % width, idx / width))
+        .collect();
+    let image = RgbImage::from_raw(width as u32, height as u32, data).unwrap();
+    let format = match format {
+        OutputFormat::Ppm => ImageFormat::Pnm,
+        OutputFormat::Jpeg => ImageFormat::Jpeg,
+        _ => ImageFormat::Png,
+    };
+    image.save_with_format(path, format)?;
+
+    Ok(())
+}
+
+// a bright color that tells `id` apart from its neighbours, and black for no ID
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::ZERO;
+    }
+
+    // golden ratio steps around the hue circle never land close to each other
+    let hue = (id as f32 * 0.618_034).fract() * 6.;
+    let x = 1. - (hue % 2. - 1.).abs();
+    match hue as u32 {
+        0 => Color::new(1., x, 0.),
+        1 => Color::new(x, 1., 0.),
+        2 => Color::new(0., 1., x),
+        3 => Color::new(0., x, 1.),
+        4 => Color::new(x, 0., 1.),
+        _ => Color::new(1., 0., x),
+    }
+}
+
+// numbers `path` for one AOV, turning `out/image.png` into `out/image_depth.png`
+pub fn aov_path<P: AsRef<Path>>(path: P, aov: Aov) -> PathBuf {
+    let path = path.as_ref();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}_{}", aov.name());
+    if let Some(ext) = path.extension() {
+        name = format!("{name}.{}", ext.to_string_lossy());
+    }
+
+    path.with_file_name(name)
+}
+
+fn save_linear(
+    pixels: &[Color],
+    width: usize,
+    height: usize,
+    path: &Path,
+    format: OutputFormat,
+) -> Result<(), OutputError> {
+    if format == OutputFormat::Hdr {
+        let out = BufWriter::new(File::create(path)?);
+        let data: Vec<_> = pixels.iter().map(|c| Rgb(*c.as_array())).collect();
+        HdrEncoder::new(out).encode(&data, width, height)?;
+    } else {
+        let data = pixels.iter().flat_map(|c| *c.as_array()).collect();
+        Rgb32FImage::from_raw(width as u32, height as u32, data)
+            .unwrap()
+            .save_with_format(path, ImageFormat::OpenExr)?;
+    }
+
+    Ok(())
+}
+
+// the number of samples taken for each pixel of `film`: as raw counts in linear formats, and
+// otherwise as shades of gray up to white for the most sampled pixel
+pub fn save_sample_map<P: AsRef<Path>>(
+    film: &Film,
+    path: P,
+    format: OutputFormat,
+) -> Result<(), OutputError> {
+    let (width, height) = (film.width(), film.height());
+    let counts = film.sample_counts();
+
+    if format.is_linear() {
+        let mut map = Film::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                map.add_sample(x, y, Color::splat(counts[y * width + x] as f32));
+            }
+        }
+        return save_with_format(&map, path, format);
+    }
+
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+    let data = counts
+        .iter()
+        .map(|&n| (n as f32 / max as f32 * 255.).round() as u8)
+        .collect();
+    let image = GrayImage::from_raw(width as u32, height as u32, data).unwrap();
+    let format = match format {
+        OutputFormat::Ppm => ImageFormat::Pnm,
+        OutputFormat::Jpeg => ImageFormat::Jpeg,
+        _ => ImageFormat::Png,
+    };
+    image.save_with_format(path, format)?;
+
+    Ok(())
+}
+
+// numbers `path` for one frame of an animation, turning `out/frame.png` into `out/frame_0001.png`
+pub fn frame_path<P: AsRef<Path>>(path: P, frame: usize) -> PathBuf {
+    let path = path.as_ref();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}_{frame:04}");
+    if let Some(ext) = path.extension() {
+        name = format!("{name}.{}", ext.to_string_lossy());
+    }
+
+    path.with_file_name(name)
+}
+
+pub fn write_ppm(
+    out: &mut dyn Write,
+    film: &Film,
+    tone_mapper: &ToneMapper,
+) -> std::io::Result<()> {
+    write!(out, "P3\n{} {}\n255\n", film.width(), film.height())?;
+    for Rgb([r, g, b]) in to_rgb8(film, tone_mapper).pixels() {
+        writeln!(out, "{r} {g} {b}")?;
+    }
+
+    Ok(())
+}
+
+pub fn to_rgb8(film: &Film, tone_mapper: &ToneMapper) -> RgbImage {
+    let width = film.width();
+    let data = film
+        .pixels()
+        .into_iter()
+        .enumerate()
+        .flat_map(|(idx, c)| tone_mapper.to_rgb8(c, idx % width, idx / width))
+        .collect();
+    RgbImage::from_raw(film.width() as u32, film.height() as u32, data).unwrap()
+}
+
+pub fn to_rgb32f(film: &Film) -> Rgb32FImage {
+    let data = film.pixels().iter().flat_map(|c| *c.as_array()).collect();
+    Rgb32FImage::from_raw(film.width() as u32, film.height() as u32, data).unwrap()
+}
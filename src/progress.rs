@@ -0,0 +1,45 @@
+use crate::{film::Film, tiles::Tile};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    // counting from 0; renders that aren't progressive only have one pass
+    pub pass: usize,
+    // of the current pass
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+}
+
+// Gets told about a render as it goes, e.g. to draw a progress bar or show the image so far.
+// Tiles finish on the render threads, but the calls are never made concurrently.
+pub trait RenderObserver: Sync {
+    // before any of `tiles` is rendered, in the order every pass hands them out
+    fn started(&self, _tiles: &[Tile]) {}
+
+    // `film` holds every tile finished so far, `tile` included; tiles are added in the order of
+    // `tiles`, so one that finishes early is reported once those before it are done
+    fn tile_finished(&self, _tile: &Tile, _progress: Progress, _film: &Film) {}
+
+    // `film` holds every pass so far, including one cut short by a time limit
+    fn pass_finished(&self, _pass: usize, _film: &Film) {}
+
+    fn finished(&self, _film: &Film) {}
+}
+
+// renders silently
+impl RenderObserver for () {}
+
+impl<F: Fn(&Tile, Progress, &Film) + Sync> RenderObserver for F {
+    fn tile_finished(&self, tile: &Tile, progress: Progress, film: &Film) {
+        self(tile, progress, film)
+    }
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        if self.tiles_total == 0 {
+            1.
+        } else {
+            self.tiles_done as f32 / self.tiles_total as f32
+        }
+    }
+}
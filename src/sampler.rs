@@ -0,0 +1,488 @@
+use rand::{Error, RngCore};
+
+use crate::units::RenderRng;
+
+mod blue_noise;
+
+// Where the random numbers of a path come from. A sampler is asked for points in [0, 1)², one
+// per pair of dimensions of each sample: the camera uses the first few for the position in the
+// pixel, on the lens and in time, and every bounce gets a few more for the material and light
+// sampling (see `SampleRng`). Samplers that spread each dimension evenly over a pixel's samples
+// converge faster than independent random numbers.
+pub trait Sampler: Sync {
+    fn sample_2d(&self, sample: &PixelSample, dimension: u32) -> [f32; 2];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelSample {
+    pub pixel: [u32; 2],
+    // counting every sample of the pixel, across passes
+    pub index: u32,
+    // how many samples the pixel is expected to get in total
+    pub samples: u32,
+    pub seed: u64,
+}
+
+// Uniform random numbers, as if there was no sampler.
+pub struct Independent;
+
+// Jittered samples in a grid of strata, so every pixel's samples cover each pair of dimensions
+// evenly. Strata are visited in a random order, and once every stratum has a sample (when more
+// samples are taken than expected) the next round starts.
+pub struct Stratified;
+
+// The Halton sequence, with a prime base for each dimension. The digits are permuted randomly per
+// pixel, which also breaks up the patterns that pairs of large bases fall into.
+pub struct Halton {
+    primes: Vec<u32>,
+}
+
+// The first two dimensions of the Sobol sequence for every pair of dimensions, shuffled and
+// Owen-scrambled differently per pixel and dimension (Burley 2020).
+pub struct Sobol;
+
+// The same scrambled Sobol points in every pixel, rotated per pixel by a blue-noise mask, so the
+// error left in neighbouring pixels differs as much as possible and is spread as fine grain
+// rather than blotches (Georgiev and Fajardo 2016).
+pub struct BlueNoise;
+
+// Draws the random numbers for one sample from a sampler. Consumers of random numbers ask for
+// a range of dimensions first; the numbers drawn past that range come from a pseudo-random
+// generator instead, so variable numbers of draws don't shift the dimensions used later.
+pub struct SampleRng<'a> {
+    sampler: &'a dyn Sampler,
+    sample: PixelSample,
+    dimension: u32,
+    end: u32,
+    // the last pair of dimensions drawn, by its index
+    pair: Option<(u32, [f32; 2])>,
+    fallback: &'a mut RenderRng,
+}
+
+// the largest f32 below 1
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON / 2.;
+// Halton sequences with larger bases are too regular to be of use
+const HALTON_DIMENSIONS: usize = 64;
+
+pub fn from_name(name: &str) -> Option<Box<dyn Sampler>> {
+    match name {
+        "independent" => Some(Box::new(Independent)),
+        "stratified" => Some(Box::new(Stratified)),
+        "halton" => Some(Box::new(Halton::new())),
+        "sobol" => Some(Box::new(Sobol)),
+        "blue_noise" => Some(Box::new(BlueNoise)),
+        _ => None,
+    }
+}
+
+impl PixelSample {
+    fn pixel_seed(&self, dimension: u32) -> u64 {
+        let [x, y] = self.pixel;
+        hash(&[self.seed, (x as u64) << 32 | y as u64, dimension as u64])
+    }
+}
+
+impl Sampler for Independent {
+    fn sample_2d(&self, sample: &PixelSample, dimension: u32) -> [f32; 2] {
+        let bits = hash(&[sample.pixel_seed(dimension), sample.index as u64]);
+        [to_unit(bits as u32), to_unit((bits >> 32) as u32)]
+    }
+}
+
+impl Sampler for Stratified {
+    fn sample_2d(&self, sample: &PixelSample, dimension: u32) -> [f32; 2] {
+        let side = (sample.samples.max(1) as f32).sqrt().ceil() as u32;
+        let strata = side * side;
+        let round = sample.index / strata;
+
+        let seed = hash(&[sample.pixel_seed(dimension), round as u64]);
+        let stratum = permute(sample.index % strata, strata, seed as u32);
+        let jitter = hash(&[seed, sample.index as u64]);
+
+        [
+            ((stratum % side) as f32 + to_unit(jitter as u32)) / side as f32,
+            ((stratum / side) as f32 + to_unit((jitter >> 32) as u32)) / side as f32,
+        ]
+        .map(|u| u.min(ONE_MINUS_EPSILON))
+    }
+}
+
+impl Halton {
+    pub fn new() -> Self {
+        let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut n = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().all(|&p| n % p != 0) {
+                primes.push(n);
+            }
+            n += 1;
+        }
+
+        Self { primes }
+    }
+}
+
+impl Default for Halton {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sampler for Halton {
+    fn sample_2d(&self, sample: &PixelSample, dimension: u32) -> [f32; 2] {
+        let (a, b) = (2 * dimension as usize, 2 * dimension as usize + 1);
+        if b >= self.primes.len() {
+            return Independent.sample_2d(sample, dimension);
+        }
+
+        let seed = sample.pixel_seed(dimension);
+        [
+            scrambled_radical_inverse(self.primes[a], sample.index, seed),
+            scrambled_radical_inverse(self.primes[b], sample.index, mix(seed)),
+        ]
+        .map(|u| u.min(ONE_MINUS_EPSILON))
+    }
+}
+
+impl Sampler for Sobol {
+    fn sample_2d(&self, sample: &PixelSample, dimension: u32) -> [f32; 2] {
+        sobol_owen(sample.index, sample.pixel_seed(dimension)).map(to_unit)
+    }
+}
+
+impl Sampler for BlueNoise {
+    fn sample_2d(&self, sample: &PixelSample, dimension: u32) -> [f32; 2] {
+        let seed = hash(&[sample.seed, dimension as u64]);
+        let point = sobol_owen(sample.index, seed).map(to_unit);
+
+        // each coordinate is rotated by a different part of the mask
+        let [x, y] = sample.pixel;
+        let offsets = [seed, seed >> 32].map(|bits| bits as u32 as usize);
+        [0, 1].map(|axis| {
+            let offset = offsets[axis];
+            let rotation = blue_noise::value(x as usize + offset, y as usize + (offset >> 16));
+            let u = point[axis] + rotation;
+            (if u >= 1. { u - 1. } else { u }).min(ONE_MINUS_EPSILON)
+        })
+    }
+}
+
+impl<'a> SampleRng<'a> {
+    pub fn new(sampler: &'a dyn Sampler, sample: PixelSample, fallback: &'a mut RenderRng) -> Self {
+        Self {
+            sampler,
+            sample,
+            dimension: 0,
+            end: 0,
+            pair: None,
+            fallback,
+        }
+    }
+
+    // the next `count` numbers come from the sampler's dimensions starting at `first`
+    pub fn start_dimensions(&mut self, first: u32, count: u32) {
+        self.dimension = first;
+        self.end = first + count;
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        if self.dimension >= self.end {
+            return to_unit(self.fallback.next_u32());
+        }
+
+        let index = self.dimension / 2;
+        let pair = match self.pair {
+            Some((i, pair)) if i == index => pair,
+            _ => {
+                let pair = self.sampler.sample_2d(&self.sample, index);
+                self.pair = Some((index, pair));
+                pair
+            }
+        };
+        let u = pair[self.dimension as usize % 2];
+        self.dimension += 1;
+
+        u
+    }
+}
+
+// Every random number drawn through `Rng` maps to one dimension, as long as it takes at most 32
+// bits: floats and ranges of small integers do.
+impl RngCore for SampleRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_f32() as f64 * (1u64 << 32) as f64) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.fallback.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.fallback.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fallback.try_fill_bytes(dest)
+    }
+}
+
+// the 24 most significant bits as a float in [0, 1)
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x243F_6A88_85A3_08D3, |h, &v| {
+        mix(h ^ mix(v.wrapping_add(0x9E37_79B9_7F4A_7C15)))
+    })
+}
+
+// the SplitMix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+// a pseudo-random permutation of [0, len) picked by `seed` (Kensler 2013)
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    // cycle walking until the hash lands in range
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+
+    (i + seed) % len
+}
+
+// mirrors the digits of `i` in `base` around the decimal point, passing each through a
+// permutation picked by `seed` and its position; leading zeros are permuted too, until they're too
+// small to matter
+fn scrambled_radical_inverse(base: u32, mut i: u32, seed: u64) -> f32 {
+    let inv_base = 1. / base as f64;
+    let (mut digits, mut scale, mut position) = (0., inv_base, 0);
+    while scale > f32::EPSILON as f64 / 2. {
+        let digit = permute(i % base, base, hash(&[seed, position]) as u32);
+        digits += digit as f64 * scale;
+        i /= base;
+        scale *= inv_base;
+        position += 1;
+    }
+
+    digits as f32
+}
+
+// point `index` of the first two Sobol dimensions, as fixed point fractions
+fn sobol_owen(index: u32, seed: u64) -> [u32; 2] {
+    let index = nested_uniform_scramble(index, seed as u32);
+
+    // the first dimension is the van der Corput sequence, the second has its direction numbers
+    // given by the Pascal matrix
+    let x = index.reverse_bits();
+    let (mut y, mut v, mut i) = (0, 1 << 31, index);
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+
+    let seeds = [mix(seed) as u32, (mix(seed) >> 32) as u32];
+    [
+        nested_uniform_scramble(x, seeds[0]),
+        nested_uniform_scramble(y, seeds[1]),
+    ]
+}
+
+// an Owen scramble of the bits of `x`, read as a binary fraction
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// a hash in which each bit only depends on the bits below it
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samplers() -> [(&'static str, Box<dyn Sampler>); 5] {
+        ["independent", "stratified", "halton", "sobol", "blue_noise"]
+            .map(|name| (name, from_name(name).unwrap()))
+    }
+
+    fn pixel_sample(pixel: [u32; 2], index: u32, samples: u32) -> PixelSample {
+        PixelSample {
+            pixel,
+            index,
+            samples,
+            seed: 7,
+        }
+    }
+
+    // the first `samples` points of dimension pair `dimension` in one pixel
+    fn points(sampler: &dyn Sampler, samples: u32, dimension: u32) -> Vec<[f32; 2]> {
+        (0..samples)
+            .map(|index| sampler.sample_2d(&pixel_sample([5, 9], index, samples), dimension))
+            .collect()
+    }
+
+    // whether every cell of an `nx` by `ny` grid holds exactly one of `points`
+    fn one_per_cell(points: &[[f32; 2]], nx: u32, ny: u32) -> bool {
+        let mut cells = vec![0; (nx * ny) as usize];
+        for [x, y] in points {
+            let cell = (y * ny as f32) as u32 * nx + (x * nx as f32) as u32;
+            cells[cell as usize] += 1;
+        }
+        cells.iter().all(|&count| count == 1)
+    }
+
+    #[test]
+    fn samples_are_in_the_unit_square() {
+        for (name, sampler) in samplers() {
+            for dimension in [0, 1, 5, 31, 40] {
+                for pixel in [[0, 0], [17, 3], [1023, 767]] {
+                    for index in (0..300).chain([4095, 65_536, u32::MAX]) {
+                        let sample = pixel_sample(pixel, index, 64);
+                        for u in sampler.sample_2d(&sample, dimension) {
+                            assert!((0. ..1.).contains(&u), "{name} gave {u}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_deterministic() {
+        for (name, sampler) in samplers() {
+            for index in 0..32 {
+                let sample = pixel_sample([12, 34], index, 16);
+                let point = sampler.sample_2d(&sample, 3);
+                assert_eq!(
+                    point,
+                    from_name(name).unwrap().sample_2d(&sample, 3),
+                    "{name}"
+                );
+
+                // anything else in the sample changes it
+                let other_seed = PixelSample { seed: 8, ..sample };
+                let other_pixel = pixel_sample([13, 34], index, 16);
+                assert_ne!(point, sampler.sample_2d(&other_seed, 3), "{name}");
+                assert_ne!(point, sampler.sample_2d(&other_pixel, 3), "{name}");
+                assert_ne!(point, sampler.sample_2d(&sample, 4), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum() {
+        for dimension in 0..4 {
+            assert!(one_per_cell(&points(&Stratified, 16, dimension), 4, 4));
+            // past the expected count, the next round fills every stratum again
+            let second_round: Vec<_> = (16..32)
+                .map(|index| Stratified.sample_2d(&pixel_sample([5, 9], index, 16), dimension))
+                .collect();
+            assert!(one_per_cell(&second_round, 4, 4));
+        }
+
+        // with a count that isn't a square, the strata cover a little more
+        let points = points(&Stratified, 10, 0);
+        let mut cells: Vec<_> = points
+            .iter()
+            .map(|[x, y]| ((x * 4.) as u32, (y * 4.) as u32))
+            .collect();
+        cells.sort_unstable();
+        cells.dedup();
+        assert_eq!(cells.len(), 10);
+    }
+
+    #[test]
+    fn halton_prefixes_are_stratified() {
+        let halton = Halton::new();
+
+        // the first two dimensions have bases 2 and 3, so every block of 2^i 3^j points puts
+        // one in each cell of a 2^i by 3^j grid
+        let first = points(&halton, 432, 0);
+        for (nx, ny) in [(8, 1), (1, 27), (2, 3), (4, 9), (8, 27)] {
+            let n = (nx * ny) as usize;
+            assert!(one_per_cell(&first[..n], nx, ny), "{nx}x{ny}");
+            assert!(one_per_cell(&first[n..2 * n], nx, ny), "{nx}x{ny}");
+        }
+
+        // and the next two 5 and 7
+        assert!(one_per_cell(&points(&halton, 35, 1), 5, 7));
+    }
+
+    #[test]
+    fn sobol_prefixes_are_nets() {
+        // every power of two points form a (0, m, 2)-net: each box of area 2^-m, whatever its
+        // shape, holds exactly one
+        for dimension in 0..4 {
+            let points = points(&Sobol, 64, dimension);
+            for m in 0..=6 {
+                let n = 1 << m;
+                for blocks in points.chunks(n) {
+                    for a in 0..=m {
+                        assert!(
+                            one_per_cell(blocks, 1 << a, 1 << (m - a)),
+                            "m = {m}, a = {a}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_pixels_rotate_the_same_points() {
+        let points = |pixel| -> Vec<[f32; 2]> {
+            (0..16)
+                .map(|index| BlueNoise.sample_2d(&pixel_sample(pixel, index, 16), 2))
+                .collect()
+        };
+
+        let (a, b) = (points([0, 0]), points([3, 60]));
+        for axis in 0..2 {
+            let rotation = (b[0][axis] - a[0][axis]).rem_euclid(1.);
+            for (a, b) in a.iter().zip(&b) {
+                let offset = (b[axis] - a[axis] - rotation).rem_euclid(1.);
+                assert!(offset.min(1. - offset) < 1e-5, "{offset}");
+            }
+        }
+    }
+}
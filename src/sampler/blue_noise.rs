@@ -0,0 +1,161 @@
+use std::sync::OnceLock;
+
+use super::hash;
+
+// The mask tiles the image, so it only needs to be large enough not to repeat visibly.
+const SIZE: usize = 64;
+const PIXELS: usize = SIZE * SIZE;
+// the spread of the filter used to find clusters and voids, in pixels
+const SIGMA: f32 = 1.5;
+
+// the mask's value at (x, y), wrapping around; every value in [0, 1) appears once per tile
+pub(super) fn value(x: usize, y: usize) -> f32 {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)[(y % SIZE) * SIZE + x % SIZE]
+}
+
+// Ulichney's void-and-cluster method: every pixel is ranked by the order in which it is added to
+// a pattern that is kept as evenly spread as possible, always filling the largest gap.
+fn void_and_cluster() -> Vec<f32> {
+    let mut kernel = vec![0.; PIXELS];
+    for (i, weight) in kernel.iter_mut().enumerate() {
+        // the shortest distance on the torus
+        let [dx, dy] = [i % SIZE, i / SIZE].map(|d| d.min(SIZE - d) as f32);
+        *weight = (-(dx * dx + dy * dy) / (2. * SIGMA * SIGMA)).exp();
+    }
+
+    let mut pattern = Energy::new(&kernel);
+    // start from a random tenth of the pixels
+    let mut seed = 0;
+    while pattern.count < PIXELS / 10 {
+        seed += 1;
+        let p = hash(&[seed]) as usize % PIXELS;
+        if !pattern.set[p] {
+            pattern.toggle(p);
+        }
+    }
+
+    // spread them evenly by moving the most crowded point to the largest gap, until that gap
+    // is where the point came from
+    for _ in 0..PIXELS {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; PIXELS];
+    let initial = pattern.clone();
+
+    // the initial points rank below the rest, the most crowded ones highest
+    while pattern.count > 0 {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        ranks[cluster] = pattern.count;
+    }
+
+    // fill the largest gaps up to half the pixels
+    pattern = initial;
+    while pattern.count < PIXELS / 2 {
+        let void = pattern.largest_void();
+        ranks[void] = pattern.count;
+        pattern.toggle(void);
+    }
+
+    // past half, the empty pixels are the sparse ones: fill the most crowded of them first
+    let mut empty = Energy::new(&kernel);
+    for p in 0..PIXELS {
+        if !pattern.set[p] {
+            empty.toggle(p);
+        }
+    }
+    while empty.count > 0 {
+        let cluster = empty.tightest_cluster();
+        ranks[cluster] = PIXELS - empty.count;
+        empty.toggle(cluster);
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank as f32 + 0.5) / PIXELS as f32)
+        .collect()
+}
+
+// A binary pattern with each pixel's filtered distance to the set pixels.
+#[derive(Clone)]
+struct Energy<'a> {
+    kernel: &'a [f32],
+    set: Vec<bool>,
+    energy: Vec<f32>,
+    count: usize,
+}
+
+impl<'a> Energy<'a> {
+    fn new(kernel: &'a [f32]) -> Self {
+        Self {
+            kernel,
+            set: vec![false; PIXELS],
+            energy: vec![0.; PIXELS],
+            count: 0,
+        }
+    }
+
+    fn toggle(&mut self, p: usize) {
+        let sign = if self.set[p] { -1. } else { 1. };
+        self.set[p] = !self.set[p];
+        self.count = if self.set[p] {
+            self.count + 1
+        } else {
+            self.count - 1
+        };
+
+        let (px, py) = (p % SIZE, p / SIZE);
+        for (q, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (q % SIZE + SIZE - px) % SIZE;
+            let dy = (q / SIZE + SIZE - py) % SIZE;
+            *energy += sign * self.kernel[dy * SIZE + dx];
+        }
+    }
+
+    // the set pixel with the most set pixels around it
+    fn tightest_cluster(&self) -> usize {
+        (0..PIXELS)
+            .filter(|&p| self.set[p])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+
+    // the unset pixel with the fewest set pixels around it
+    fn largest_void(&self) -> usize {
+        (0..PIXELS)
+            .filter(|&p| !self.set[p])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_ranks_every_pixel_once() {
+        let mut ranks: Vec<usize> = (0..PIXELS)
+            .map(|p| {
+                let rank = value(p % SIZE, p / SIZE) * PIXELS as f32 - 0.5;
+                assert_eq!(rank.fract(), 0.);
+                rank as usize
+            })
+            .collect();
+        ranks.sort_unstable();
+        assert!(ranks.into_iter().eq(0..PIXELS));
+    }
+
+    #[test]
+    fn mask_wraps_around() {
+        assert_eq!(value(3, 5), value(3 + SIZE, 5 + 2 * SIZE));
+    }
+}
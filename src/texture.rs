@@ -75,8 +75,9 @@ impl Texture for ImageTexture {
         let u = uv.x.clamp(0.0, 1.0);
         let v = 1.0 - uv.y.clamp(0.0, 1.0);
 
-        let i = (u * self.image.width() as f32).floor() as u32;
-        let j = (v * self.image.height() as f32).floor() as u32;
+        // u = 1 and v = 0 land just past the last pixel
+        let i = ((u * self.image.width() as f32) as u32).min(self.image.width() - 1);
+        let j = ((v * self.image.height() as f32) as u32).min(self.image.height() - 1);
         let pixel = self.image.get_pixel(i, j).0;
 
         Color::from_array(pixel.map(|channel| channel as f32 / 255.0))
@@ -90,3 +91,61 @@ impl ImageTexture {
         Some(Self { image })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use crate::{
+        hittable::{triangle::Triangle, Hittable, Interval},
+        material::Lambertian,
+        ray::Ray,
+        units::Vector,
+    };
+
+    use super::*;
+
+    // a 2x2 image with a different color in every corner
+    fn corners() -> ImageTexture {
+        let image = RgbImage::from_fn(2, 2, |x, y| Rgb([x as u8 * 255, y as u8 * 255, 0]));
+        ImageTexture { image }
+    }
+
+    #[test]
+    fn image_edges_pick_the_last_pixels() {
+        let texture = corners();
+        let point = Point::new(0., 0., 0.);
+
+        // v = 0 is the bottom row
+        let bottom_right = texture.value(TexCoord::new(1., 0.), point);
+        assert_eq!(bottom_right, Color::new(1., 1., 0.));
+        let top_left = texture.value(TexCoord::new(0., 1.), point);
+        assert_eq!(top_left, Color::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn triangle_with_uvs_on_the_edge() {
+        let texture = corners();
+        let material = Lambertian { albedo: &texture };
+        // the whole triangle maps to the bottom right corner, as OBJ exports of flat colored
+        // faces often do
+        let triangle = Triangle::with_attributes(
+            [
+                Point::new(-1., -1., 0.),
+                Point::new(1., -1., 0.),
+                Point::new(0., 1., 0.),
+            ],
+            None,
+            [TexCoord::new(1., 0.); 3],
+            &material,
+        );
+
+        let ray = Ray {
+            origin: Point::new(0.1, -0.2, 1.),
+            direct: Vector::new(0., 0., -1.),
+            time: 0.,
+        };
+        let hit = triangle.hit(&ray, Interval::<f32>::POSITIVE).unwrap();
+        assert_eq!(hit.mat.albedo(&hit), Color::new(1., 1., 0.));
+    }
+}
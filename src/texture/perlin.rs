@@ -1,5 +1,5 @@
 use glamour::Vector3;
-use rand::{distributions::Uniform, rngs::ThreadRng, thread_rng, Rng};
+use rand::{distributions::Uniform, Rng};
 
 use crate::units::{Color, Point, TexCoord};
 
@@ -18,9 +18,9 @@ struct Perlin {
 }
 
 impl NoiseTexture {
-    pub fn new(scale: f32) -> Self {
+    pub fn new<R: Rng + ?Sized>(scale: f32, rng: &mut R) -> Self {
         Self {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale,
         }
     }
@@ -38,9 +38,7 @@ impl Perlin {
     // POINT_COUNT must be at most u8::MAX (or whatever type is in Perlin.{x, y, z})
     pub const POINT_COUNT: usize = 256;
 
-    fn new() -> Self {
-        let mut rng = thread_rng();
-
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
         let mut this = Perlin {
             ran_vec: [Vector3::ZERO; Self::POINT_COUNT],
             x: [0; Self::POINT_COUNT],
@@ -55,14 +53,14 @@ impl Perlin {
             // *vec = random_unit_vector().cast();
         }
 
-        Self::generate_perm(&mut this.x, &mut rng);
-        Self::generate_perm(&mut this.y, &mut rng);
-        Self::generate_perm(&mut this.z, &mut rng);
+        Self::generate_perm(&mut this.x, rng);
+        Self::generate_perm(&mut this.y, rng);
+        Self::generate_perm(&mut this.z, rng);
 
         this
     }
 
-    fn generate_perm(p: &mut [u8; Self::POINT_COUNT], rng: &mut ThreadRng) {
+    fn generate_perm<R: Rng + ?Sized>(p: &mut [u8; Self::POINT_COUNT], rng: &mut R) {
         for (i, p) in p.iter_mut().enumerate() {
             *p = i as _;
         }
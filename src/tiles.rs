@@ -0,0 +1,175 @@
+// A rectangle of pixels rendered as one unit of work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// The order tiles are handed out in. Rendering is parallel, so this is only roughly the order in
+// which they finish.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    // row by row from the top left
+    Scanline,
+    // outwards from the center of the image, where the subject usually is
+    #[default]
+    Spiral,
+    // along a Hilbert curve, so consecutive tiles are always neighbours
+    Hilbert,
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scanline" => Some(Self::Scanline),
+            "spiral" => Some(Self::Spiral),
+            "hilbert" => Some(Self::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+// covers a `width` by `height` image with tiles of up to `size` pixels square, in `order`
+pub fn tiles(width: usize, height: usize, size: usize, order: TileOrder) -> Vec<Tile> {
+    assert!(size > 0, "Tiles need to be at least one pixel wide!");
+    let (columns, rows) = (width.div_ceil(size), height.div_ceil(size));
+
+    let grid: Vec<(usize, usize)> = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect(),
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            let mut grid: Vec<_> = (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect();
+            grid.sort_by_key(|&(column, row)| hilbert_index(n, column, row));
+            grid
+        }
+    };
+
+    grid.into_iter()
+        .map(|(column, row)| {
+            let (x, y) = (column * size, row * size);
+            Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+// walks a square spiral around the middle of the grid, skipping the steps that fall outside it
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let count = columns * rows;
+    let mut cells = Vec::with_capacity(count);
+    let (mut x, mut y) = (((columns as isize) - 1) / 2, ((rows as isize) - 1) / 2);
+    let (mut dx, mut dy) = (1, 0);
+    let mut leg = 1;
+
+    while cells.len() < count {
+        // every two legs of the spiral get one step longer
+        for _ in 0..2 {
+            for _ in 0..leg {
+                if (0..columns as isize).contains(&x) && (0..rows as isize).contains(&y) {
+                    cells.push((x as usize, y as usize));
+                }
+                x += dx;
+                y += dy;
+            }
+            (dx, dy) = (-dy, dx);
+        }
+        leg += 1;
+    }
+
+    cells
+}
+
+// the distance along the Hilbert curve filling an `n` by `n` grid, `n` being a power of two
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+
+        // rotate the quadrant so the curve inside it starts and ends in the right corners
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            (x, y) = (y, x);
+        }
+        s /= 2;
+    }
+
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        let sizes = [
+            (1, 1),
+            (7, 1),
+            (1, 9),
+            (16, 16),
+            (64, 48),
+            (50, 13),
+            (13, 50),
+            (100, 37),
+        ];
+        for (width, height) in sizes {
+            for size in [1, 3, 8, 16, 64, 200] {
+                for order in ORDERS {
+                    let mut covered = vec![0; width * height];
+                    for tile in tiles(width, height, size, order) {
+                        assert!(tile.width > 0 && tile.width <= size);
+                        assert!(tile.height > 0 && tile.height <= size);
+                        for y in tile.y..tile.y + tile.height {
+                            for x in tile.x..tile.x + tile.width {
+                                assert!(x < width && y < height, "{tile:?} is out of bounds");
+                                covered[y * width + x] += 1;
+                            }
+                        }
+                    }
+                    assert!(
+                        covered.iter().all(|&n| n == 1),
+                        "{order:?} tiles of {size} don't cover {width}x{height} exactly once"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_the_middle() {
+        let first = tiles(100, 37, 8, TileOrder::Spiral)[0];
+        // the middle of a 13 by 5 grid
+        assert_eq!((first.x, first.y), (48, 16));
+    }
+
+    #[test]
+    fn hilbert_tiles_follow_each_other() {
+        // on square grids of a power of two tiles, the curve never jumps
+        for side in [8, 32, 64] {
+            let tiles = tiles(side, side, 4, TileOrder::Hilbert);
+            for pair in tiles.windows(2) {
+                let distance = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+                assert_eq!(distance, 4, "{pair:?}");
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{hittable::AABB, units::Point};
+use crate::{
+    hittable::AABB,
+    units::{Point, Vector},
+};
 
 pub trait Movement<T> {
     type Storage: Sync;
@@ -34,3 +37,379 @@ impl Movement<Point> for Linear {
         AABB::<f32>::from_corners(*a, *b)
     }
 }
+
+// Remaps the progress between two keys, keeping 0 and 1 in place.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Self::Linear),
+            "ease_in" => Some(Self::EaseIn),
+            "ease_out" => Some(Self::EaseOut),
+            "ease_in_out" => Some(Self::EaseInOut),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, u: f32) -> f32 {
+        match self {
+            Self::Linear => u,
+            Self::EaseIn => u * u * u,
+            Self::EaseOut => 1. - (1. - u).powi(3),
+            Self::EaseInOut => u * u * (3. - 2. * u),
+        }
+    }
+}
+
+// Points at arbitrary times, sorted by time. Before the first and after the last key, the
+// position holds still.
+pub struct Keyframes {
+    times: Vec<f32>,
+    points: Vec<Point>,
+    easing: Easing,
+}
+
+impl Keyframes {
+    pub fn new(mut keys: Vec<(f32, Point)>, easing: Easing) -> Self {
+        assert!(
+            !keys.is_empty(),
+            "Cannot move along a path without keyframes!"
+        );
+        keys.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let (times, points) = keys.into_iter().unzip();
+        Self {
+            times,
+            points,
+            easing,
+        }
+    }
+
+    // the index of the key starting the segment around `time`, with the eased progress along it
+    fn segment(&self, time: f32) -> Option<(usize, f32)> {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 || next == self.times.len() {
+            return None;
+        }
+
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        let u = (time - start) / (end - start);
+        Some((next - 1, self.easing.apply(u)))
+    }
+
+    fn clamped(&self, time: f32) -> Point {
+        if time < self.times[0] {
+            self.points[0]
+        } else {
+            self.points[self.points.len() - 1]
+        }
+    }
+}
+
+pub struct PiecewiseLinear;
+
+impl Movement<Point> for PiecewiseLinear {
+    type Storage = Keyframes;
+
+    fn get_at_time(this: &Self::Storage, time: f32) -> Point {
+        match this.segment(time) {
+            Some((i, u)) => this.points[i] + (this.points[i + 1] - this.points[i]) * u,
+            None => this.clamped(time),
+        }
+    }
+
+    // the path never leaves the box around its corners
+    fn bounding_box(this: &Self::Storage) -> AABB<f32> {
+        this.points
+            .iter()
+            .fold(AABB::EMPTY, |aabb, &p| aabb.insert(p))
+    }
+}
+
+// A smooth path through keyframes, made of one cubic Bezier curve per pair of keys.
+pub struct SplinePath {
+    keys: Keyframes,
+    // the two inner control points of every segment
+    handles: Vec<[Point; 2]>,
+}
+
+impl SplinePath {
+    // Passes through every key, with the tangent at each one parallel to the line between its
+    // neighbours (scaled to the key times, so uneven spacing doesn't overshoot).
+    pub fn catmull_rom(keys: Vec<(f32, Point)>, easing: Easing) -> Self {
+        let keys = Keyframes::new(keys, easing);
+        let (times, points) = (&keys.times, &keys.points);
+        let last = points.len() - 1;
+
+        let tangent = |i: usize| {
+            let (prev, next) = (i.saturating_sub(1), (i + 1).min(last));
+            if prev == next {
+                return Vector::ZERO;
+            }
+            (points[next] - points[prev]) / (times[next] - times[prev])
+        };
+
+        let handles = (0..last)
+            .map(|i| {
+                let dt = (times[i + 1] - times[i]) / 3.;
+                [
+                    points[i] + tangent(i) * dt,
+                    points[i + 1] - tangent(i + 1) * dt,
+                ]
+            })
+            .collect();
+
+        Self { keys, handles }
+    }
+
+    // `handles` are the inner control points of each segment between consecutive keys, in key
+    // time order
+    pub fn bezier(keys: Vec<(f32, Point)>, handles: Vec<[Point; 2]>, easing: Easing) -> Self {
+        assert_eq!(
+            handles.len() + 1,
+            keys.len(),
+            "A Bezier path needs one pair of handles between each pair of keys!"
+        );
+
+        Self {
+            keys: Keyframes::new(keys, easing),
+            handles,
+        }
+    }
+
+    fn control_points(&self, segment: usize) -> [Point; 4] {
+        let [b1, b2] = self.handles[segment];
+        [
+            self.keys.points[segment],
+            b1,
+            b2,
+            self.keys.points[segment + 1],
+        ]
+    }
+}
+
+pub struct Spline;
+
+impl Movement<Point> for Spline {
+    type Storage = SplinePath;
+
+    fn get_at_time(this: &Self::Storage, time: f32) -> Point {
+        match this.keys.segment(time) {
+            Some((i, u)) => bezier_point(this.control_points(i), u),
+            None => this.keys.clamped(time),
+        }
+    }
+
+    // each segment is bounded by its end points and wherever it turns around along an axis
+    fn bounding_box(this: &Self::Storage) -> AABB<f32> {
+        let mut aabb = <PiecewiseLinear as Movement<Point>>::bounding_box(&this.keys);
+        for segment in 0..this.handles.len() {
+            let points = this.control_points(segment);
+            for axis in 0..3 {
+                for u in bezier_extrema(points.map(|p| p[axis])) {
+                    aabb = aabb.insert(bezier_point(points, u));
+                }
+            }
+        }
+
+        aabb
+    }
+}
+
+fn bezier_point([p0, p1, p2, p3]: [Point; 4], u: f32) -> Point {
+    let v = 1. - u;
+    let [w0, w1, w2, w3] = [v * v * v, 3. * v * v * u, 3. * v * u * u, u * u * u];
+    (p0.to_vector() * w0 + p1.to_vector() * w1 + p2.to_vector() * w2 + p3.to_vector() * w3)
+        .to_point()
+}
+
+// the parameters in (0, 1) where the derivative of a 1D cubic Bezier curve vanishes
+fn bezier_extrema([p0, p1, p2, p3]: [f32; 4]) -> impl Iterator<Item = f32> {
+    // the derivative is 3 times the quadratic bezier over these differences
+    let (d0, d1, d2) = (p1 - p0, p2 - p1, p3 - p2);
+    let a = d0 - 2. * d1 + d2;
+    let b = 2. * (d1 - d0);
+    let c = d0;
+
+    let roots = if a.abs() < f32::EPSILON {
+        [(b.abs() >= f32::EPSILON).then(|| -c / b), None]
+    } else {
+        let discriminant = b * b - 4. * a * c;
+        if discriminant < 0. {
+            [None, None]
+        } else {
+            let sqrt = discriminant.sqrt();
+            [Some((-b + sqrt) / (2. * a)), Some((-b - sqrt) / (2. * a))]
+        }
+    };
+
+    roots.into_iter().flatten().filter(|u| *u > 0. && *u < 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::units::RenderRng;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+    const EASINGS: [Easing; 4] = [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+    ];
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).length() < EPSILON * a.to_vector().length().max(1.)
+    }
+
+    fn random_point(rng: &mut RenderRng) -> Point {
+        Point::new(
+            rng.gen_range(-5. ..5.),
+            rng.gen_range(-5. ..5.),
+            rng.gen_range(-5. ..5.),
+        )
+    }
+
+    // unevenly spaced keys, listed out of order
+    fn random_keys(rng: &mut RenderRng, count: usize) -> Vec<(f32, Point)> {
+        let mut time = 0.;
+        let mut keys: Vec<_> = (0..count)
+            .map(|_| {
+                time += rng.gen_range(0.05..1.);
+                (time, random_point(rng))
+            })
+            .collect();
+        keys.reverse();
+        keys.swap(0, count / 2);
+        keys
+    }
+
+    fn assert_bounded<M: Movement<Point>>(path: &M::Storage, start: f32, end: f32) {
+        let aabb = M::bounding_box(path);
+        for i in 0..=10_000 {
+            let time = start + (end - start) * i as f32 / 10_000.;
+            let p = M::get_at_time(path, time);
+            for axis in 0..3 {
+                assert!(
+                    aabb[axis].expand(EPSILON).contains(p[axis]),
+                    "{p:?} at {time} is outside of {:?} along axis {axis}",
+                    aabb[axis]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn easings_keep_their_ends() {
+        for easing in EASINGS {
+            assert_eq!(easing.apply(0.), 0., "{easing:?}");
+            assert_eq!(easing.apply(1.), 1., "{easing:?}");
+
+            let mut previous = 0.;
+            for i in 1..=100 {
+                let u = easing.apply(i as f32 / 100.);
+                assert!(u >= previous && u <= 1., "{easing:?}");
+                previous = u;
+            }
+        }
+
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_its_keys() {
+        let mut rng = RenderRng::seed_from_u64(2);
+        for count in [1, 2, 3, 7] {
+            for easing in EASINGS {
+                let keys = random_keys(&mut rng, count);
+                let path = SplinePath::catmull_rom(keys.clone(), easing);
+                for (time, point) in keys {
+                    let p = Spline::get_at_time(&path, time);
+                    assert!(close(p, point), "{p:?} != {point:?} at {time}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn paths_hold_still_outside_their_keys() {
+        let keys = vec![
+            (0.25, Point::new(1., 2., 3.)),
+            (0.75, Point::new(-1., 0., 3.)),
+        ];
+        let linear = Keyframes::new(keys.clone(), Easing::Linear);
+        let spline = SplinePath::catmull_rom(keys, Easing::EaseInOut);
+
+        for time in [-1., 0., 0.25] {
+            assert_eq!(
+                PiecewiseLinear::get_at_time(&linear, time),
+                Point::new(1., 2., 3.)
+            );
+            assert_eq!(Spline::get_at_time(&spline, time), Point::new(1., 2., 3.));
+        }
+        for time in [0.75, 1., 2.] {
+            assert_eq!(
+                PiecewiseLinear::get_at_time(&linear, time),
+                Point::new(-1., 0., 3.)
+            );
+            assert_eq!(Spline::get_at_time(&spline, time), Point::new(-1., 0., 3.));
+        }
+
+        let halfway = PiecewiseLinear::get_at_time(&linear, 0.5);
+        assert!(close(halfway, Point::new(0., 1., 3.)));
+    }
+
+    #[test]
+    fn bounds_contain_the_whole_path() {
+        let mut rng = RenderRng::seed_from_u64(3);
+        for count in [1, 2, 4, 9] {
+            for easing in EASINGS {
+                let keys = random_keys(&mut rng, count);
+                let end = keys.iter().map(|&(t, _)| t).fold(0., f32::max) + 0.5;
+
+                let linear = Keyframes::new(keys.clone(), easing);
+                assert_bounded::<PiecewiseLinear>(&linear, -0.5, end);
+
+                let spline = SplinePath::catmull_rom(keys.clone(), easing);
+                assert_bounded::<Spline>(&spline, -0.5, end);
+
+                // handles far off the keys, which the curve bends towards
+                let handles = (1..count)
+                    .map(|_| [0; 2].map(|_| (random_point(&mut rng).to_vector() * 3.).to_point()))
+                    .collect();
+                let bezier = SplinePath::bezier(keys, handles, easing);
+                assert_bounded::<Spline>(&bezier, -0.5, end);
+            }
+        }
+
+        let line = (Point::new(1., -2., 0.), Point::new(-3., 4., 0.5));
+        assert_bounded::<Linear>(&line, 0., 1.);
+    }
+
+    #[test]
+    fn bezier_bounds_reach_the_turning_points() {
+        // a curve bulging out to y = 0.75 between keys at y = 0
+        let path = SplinePath::bezier(
+            vec![(0., Point::new(0., 0., 0.)), (1., Point::new(1., 0., 0.))],
+            vec![[Point::new(0., 1., 0.), Point::new(1., 1., 0.)]],
+            Easing::Linear,
+        );
+        let aabb = Spline::bounding_box(&path);
+        assert!((aabb.y.max - 0.75).abs() < EPSILON, "{:?}", aabb.y);
+        assert!(aabb.y.min.abs() < EPSILON, "{:?}", aabb.y);
+    }
+}
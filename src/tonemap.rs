@@ -0,0 +1,273 @@
+use rand::Rng;
+
+use crate::units::{rng_stream, Color};
+
+// How the film's linear radiance becomes 8-bit sRGB: scaled by the exposure, compressed into
+// [0, 1] by a tone mapping operator, encoded with the sRGB transfer function and, optionally,
+// dithered before quantization so smooth gradients don't band.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ToneMapper {
+    // in stops: every +1 doubles the brightness
+    pub exposure: f32,
+    pub operator: Operator,
+    pub dither: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Operator {
+    // cuts everything above 1 off, which blows out lights and highlights
+    #[default]
+    Clamp,
+    // x / (1 + x) per channel, which never quite reaches white
+    Reinhard,
+    // Stephen Hill's fit of the ACES reference and sRGB output transforms, with a filmic toe and
+    // shoulder that also desaturates highlights
+    Aces,
+    // Troy Sobotka's AgX, as approximated by Benjamin Wrensch: a log encoding and a contrast curve
+    // in a tighter gamut, which lets very bright colors fade to white instead of shifting hue
+    Agx,
+}
+
+impl Operator {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "clamp" => Some(Self::Clamp),
+            "reinhard" => Some(Self::Reinhard),
+            "aces" => Some(Self::Aces),
+            "agx" => Some(Self::Agx),
+            _ => None,
+        }
+    }
+
+    // maps scene radiance to display radiance in [0, 1], both linear
+    pub fn apply(self, c: Color) -> Color {
+        let mapped = match self {
+            Self::Clamp => c,
+            Self::Reinhard => Color::from_array(c.as_array().map(|x| x / (1. + x))),
+            Self::Aces => aces(c),
+            Self::Agx => agx(c),
+        };
+        Color::from_array(mapped.as_array().map(|x| x.clamp(0., 1.)))
+    }
+}
+
+impl ToneMapper {
+    // the display radiance for `c`, before it is encoded
+    pub fn map(&self, c: Color) -> Color {
+        self.operator
+            .apply(c.max(Color::ZERO) * self.exposure.exp2())
+    }
+
+    // `c` as the 8-bit sRGB color of pixel (x, y), which seeds the dithering
+    pub fn to_rgb8(&self, c: Color, x: usize, y: usize) -> [u8; 3] {
+        let encoded = self.map(c).as_array().map(srgb_encode);
+        if !self.dither {
+            return encoded.map(quantize);
+        }
+
+        // noise with a triangular distribution over ±1 step hides the steps without adding a
+        // visible pattern
+        let mut rng = rng_stream(0, ((y as u64) << 32) | x as u64);
+        encoded.map(|v| {
+            let noise = rng.gen::<f32>() + rng.gen::<f32>() - 1.;
+            quantize(v + noise / 256.)
+        })
+    }
+}
+
+// the sRGB transfer function, from linear to encoded values in [0, 1]
+pub fn srgb_encode(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn quantize(v: f32) -> u8 {
+    (v.clamp(0., 1.) * 255.999).floor() as u8
+}
+
+fn aces(c: Color) -> Color {
+    // from sRGB to the ACES rendering space, and back after the curve
+    const INPUT: [[f32; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let v = mul(INPUT, *c.as_array()).map(|v| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.432951) + 0.238081;
+        a / b
+    });
+    Color::from_array(mul(OUTPUT, v))
+}
+
+fn agx(c: Color) -> Color {
+    const INSET: [[f32; 3]; 3] = [
+        [0.84247906, 0.0784336, 0.079223745],
+        [0.042328242, 0.87846864, 0.07916613],
+        [0.042375655, 0.0784336, 0.879143],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196879, -0.09802088, -0.09902974],
+        [-0.052896852, 1.1519031, -0.098961177],
+        [-0.052971636, -0.09804345, 1.1510737],
+    ];
+    // the range of exposures around middle gray that the curve spans
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let v = mul(INSET, *c.as_array()).map(|v| {
+        let x = ((v.max(1e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0., 1.);
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // the curve produces display-encoded values, which are decoded again for the sRGB encoding
+    Color::from_array(mul(OUTSET, v).map(|v| v.max(0.).powf(2.2)))
+}
+
+fn mul(m: [[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [Operator; 4] = [
+        Operator::Clamp,
+        Operator::Reinhard,
+        Operator::Aces,
+        Operator::Agx,
+    ];
+
+    fn colors() -> impl Iterator<Item = Color> {
+        let values = [0., 1e-4, 0.01, 0.18, 0.5, 1., 2., 16., 1000., 1e8];
+        values.into_iter().flat_map(move |r| {
+            values
+                .into_iter()
+                .flat_map(move |g| [0., 0.3, 50.].map(|b| Color::new(r, g, b)))
+        })
+    }
+
+    #[test]
+    fn srgb_encoding_endpoints_and_breakpoint() {
+        assert_eq!(srgb_encode(0.), 0.);
+        assert!((srgb_encode(1.) - 1.).abs() < 1e-6);
+        assert!((srgb_encode(0.18) - 0.4613).abs() < 1e-4);
+
+        // the linear and power segments meet at the breakpoint
+        let linear = 12.92 * 0.0031308;
+        assert_eq!(srgb_encode(0.0031308), linear);
+        assert!((srgb_encode(0.0031309) - linear).abs() < 1e-5);
+
+        let mut previous = -1.;
+        for i in 0..=1000 {
+            let encoded = srgb_encode(i as f32 / 1000.);
+            assert!(encoded > previous);
+            previous = encoded;
+        }
+    }
+
+    #[test]
+    fn operators_map_black_to_black() {
+        for operator in OPERATORS {
+            assert_eq!(operator.apply(Color::ZERO), Color::ZERO, "{operator:?}");
+        }
+    }
+
+    #[test]
+    fn operators_stay_in_range() {
+        for operator in OPERATORS {
+            for c in colors() {
+                for x in operator.apply(c).as_array() {
+                    assert!((0. ..=1.).contains(x), "{operator:?} maps {c:?} to {x}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn filmic_operators_brighten_monotonically() {
+        for operator in [Operator::Aces, Operator::Agx] {
+            let mut previous = -1.;
+            for i in 0..200 {
+                let gray = Color::splat((i as f32 / 10. - 10.).exp2());
+                let mapped = operator.apply(gray).as_array()[1];
+                assert!(mapped >= previous, "{operator:?}");
+                previous = mapped;
+            }
+            // and bright grays come close to white
+            assert!(previous > 0.95, "{operator:?} tops out at {previous}");
+        }
+    }
+
+    #[test]
+    fn exposure_scales_by_powers_of_two() {
+        for exposure in [-3., -1., 0., 0.5, 2.] {
+            let tone_mapper = ToneMapper {
+                exposure,
+                ..Default::default()
+            };
+            let mapped = tone_mapper.map(Color::new(0.01, 0.02, 0.04));
+            let expected = Color::new(0.01, 0.02, 0.04) * f32::exp2(exposure);
+            assert!((mapped - expected)
+                .as_array()
+                .iter()
+                .all(|d| d.abs() < 1e-6));
+        }
+
+        // negative radiance from filters with negative lobes is cut off first
+        let tone_mapper = ToneMapper::default();
+        assert_eq!(
+            tone_mapper.map(Color::new(-1., 0.5, 2.)),
+            Color::new(0., 0.5, 1.)
+        );
+    }
+
+    #[test]
+    fn quantization_covers_every_value() {
+        let tone_mapper = ToneMapper::default();
+        assert_eq!(tone_mapper.to_rgb8(Color::ZERO, 0, 0), [0; 3]);
+        assert_eq!(tone_mapper.to_rgb8(Color::ONE, 0, 0), [255; 3]);
+        assert_eq!(tone_mapper.to_rgb8(Color::splat(-1.), 0, 0), [0; 3]);
+        assert_eq!(tone_mapper.to_rgb8(Color::splat(5.), 0, 0), [255; 3]);
+    }
+
+    #[test]
+    fn dithering_moves_values_by_at_most_one_step() {
+        let plain = ToneMapper::default();
+        let dithered = ToneMapper {
+            dither: true,
+            ..plain
+        };
+
+        let mut changed = 0;
+        for i in 0..=256 {
+            let c = Color::new(i as f32 / 256., (i as f32 / 256.).powi(3), 0.5);
+            for (x, y) in [(0, 0), (1, 0), (17, 9), (1919, 1079)] {
+                let expected = plain.to_rgb8(c, x, y);
+                let actual = dithered.to_rgb8(c, x, y);
+                for (a, b) in actual.into_iter().zip(expected) {
+                    assert!(a.abs_diff(b) <= 1, "{a} and {b} for {c:?}");
+                    changed += (a != b) as usize;
+                }
+
+                // the same pixel always gets the same noise
+                assert_eq!(dithered.to_rgb8(c, x, y), actual);
+            }
+        }
+        assert!(changed > 0);
+
+        // black and white stay put
+        assert_eq!(dithered.to_rgb8(Color::ZERO, 3, 4), [0; 3]);
+    }
+}
@@ -1,7 +1,12 @@
-use std::io::Write;
+use std::{
+    f32::consts::{FRAC_PI_2, FRAC_PI_4, PI},
+    io::Write,
+};
 
 use glamour::{Point2, Point3, Unit, Vector3};
-use rand::{distributions::Uniform, thread_rng, Rng};
+use rand::{distributions::Uniform, rngs::SmallRng, Rng, SeedableRng};
+
+use crate::tonemap::ToneMapper;
 
 pub struct ColorSpace;
 impl Unit for ColorSpace {
@@ -25,18 +30,32 @@ impl Unit for TextureSpace {
 
 pub type TexCoord = Point2<TextureSpace>;
 
-pub fn write_color(out: &mut dyn Write, c: Color) {
-    let floats = c.as_array();
-    let ints = floats
-        .map(f32::sqrt) // linear to gamma
-        .map(|f| (f.clamp(0., 1.) * 255.999).floor() as u32) // convert to integer in [0, 255]
-        ;
+// every random decision made while rendering draws from one of these, so that a render is
+// reproducible from its seed
+pub type RenderRng = SmallRng;
+
+// an independent stream for e.g. each pixel, derived from a render-wide seed
+pub fn rng_stream(seed: u64, stream: u64) -> RenderRng {
+    RenderRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+// `c` in sRGB without any tone mapping, see `ToneMapper` for more control
+pub fn color_to_rgb8(c: Color) -> [u8; 3] {
+    ToneMapper::default().to_rgb8(c, 0, 0)
+}
+
+// relative luminance of a linear Rec. 709 color
+pub fn luminance(c: Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+pub fn write_color(out: &mut dyn Write, c: Color) -> std::io::Result<()> {
+    let ints = color_to_rgb8(c);
 
-    writeln!(out, "{} {} {}", ints[0], ints[1], ints[2]).unwrap()
+    writeln!(out, "{} {} {}", ints[0], ints[1], ints[2])
 }
 
-pub fn random_in_unit_sphere() -> Vector {
-    let mut rng = thread_rng();
+pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Vector {
     let distr = Uniform::new(-1., 1.);
     loop {
         let v = Vector::new(rng.sample(distr), rng.sample(distr), rng.sample(distr));
@@ -46,12 +65,17 @@ pub fn random_in_unit_sphere() -> Vector {
     }
 }
 
-pub fn random_unit_vector() -> Vector {
-    random_in_unit_sphere().normalize()
+// Maps two uniform numbers onto the sphere, rather than rejecting points outside of it, so that
+// evenly spread numbers give evenly spread directions.
+pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vector {
+    let z = 1. - 2. * rng.gen::<f32>();
+    let phi = 2. * PI * rng.gen::<f32>();
+    let r = (1. - z * z).max(0.).sqrt();
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_on_hemisphere(normal: &Vector) -> Vector {
-    let v = random_unit_vector();
+pub fn random_on_hemisphere<R: Rng + ?Sized>(normal: &Vector, rng: &mut R) -> Vector {
+    let v = random_unit_vector(rng);
     if v.dot(*normal) > 0. {
         v
     } else {
@@ -59,15 +83,34 @@ pub fn random_on_hemisphere(normal: &Vector) -> Vector {
     }
 }
 
-pub fn random_in_unit_disk() -> Vector {
-    let mut rng = thread_rng();
-    let distr = Uniform::new(-1., 1.);
-    loop {
-        let v = Vector::new(rng.sample(distr), rng.sample(distr), 0.);
-        if v.length_squared() < 1. {
-            break v;
-        }
+// Shirley and Chiu's concentric mapping of the square onto the disk, which keeps neighbouring
+// points close together
+pub fn random_in_unit_disk<R: Rng + ?Sized>(rng: &mut R) -> Vector {
+    let (x, y) = (2. * rng.gen::<f32>() - 1., 2. * rng.gen::<f32>() - 1.);
+    if x == 0. && y == 0. {
+        return Vector::ZERO;
     }
+
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+    Vector::new(r * theta.cos(), r * theta.sin(), 0.)
+}
+
+// an orthonormal basis `[u, v, w]` whose third axis is the direction of `w`
+pub fn orthonormal_basis(w: Vector) -> [Vector; 3] {
+    let w = w.normalize();
+    let a = if w.x.abs() > 0.9 {
+        Vector::new(0., 1., 0.)
+    } else {
+        Vector::new(1., 0., 0.)
+    };
+    let v = w.cross(a).normalize();
+    let u = w.cross(v);
+
+    [u, v, w]
 }
 
 pub fn reflect(v: &Vector, n: &Vector) -> Vector {